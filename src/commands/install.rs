@@ -14,9 +14,12 @@ use crate::lockfile::Lockfile;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
 use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::resolution::resolve;
+use crate::resolution::{resolve, ResolveOptions};
 
-use super::utils::{generate_dependency_changes, render_update_difference};
+use super::utils::{
+    check_licenses, deprecation_warnings, duplicate_warnings, generate_dependency_changes,
+    render_update_difference,
+};
 use super::GlobalOptions;
 
 /// Install all of the dependencies of this project.
@@ -29,6 +32,11 @@ pub struct InstallSubcommand {
     /// Flag to error if the lockfile does not match with the latest dependencies.
     #[structopt(long = "locked")]
     pub locked: bool,
+
+    /// Install the lowest versions that satisfy each requirement, ignoring
+    /// the versions in the lockfile.
+    #[structopt(long = "minimal-versions")]
+    pub minimal_versions: bool,
 }
 
 impl InstallSubcommand {
@@ -50,8 +58,23 @@ impl InstallSubcommand {
 
         let mut package_sources = PackageSourceMap::new(default_registry);
         package_sources.add_fallbacks()?;
+        package_sources.add_registries(&manifest)?;
+        package_sources.add_patches(&manifest, &self.project_path)?;
+
+        let locked_ids: BTreeSet<PackageId> = lockfile.as_ids().collect();
+
+        // The lockfile usually holds newer versions than the minimal ones, so
+        // it's only used as a starting point for normal installs.
+        let try_to_use = if self.minimal_versions {
+            BTreeSet::new()
+        } else {
+            locked_ids.clone()
+        };
 
-        let try_to_use = lockfile.as_ids().collect();
+        let options = ResolveOptions {
+            minimal_versions: self.minimal_versions,
+            ..Default::default()
+        };
 
         let progress = ProgressBar::new(0).with_style(
             ProgressStyle::with_template("{spinner:.cyan}{wide_msg}")?.tick_chars("⠁⠈⠐⠠⠄⠂ "),
@@ -66,12 +89,12 @@ impl InstallSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-            let latest_graph = resolve(&manifest, &BTreeSet::new(), &package_sources)?;
+            let latest_graph = resolve(&manifest, &BTreeSet::new(), &package_sources, &options)?;
 
-            if try_to_use != latest_graph.activated {
+            if locked_ids != latest_graph.activated {
                 progress.finish_and_clear();
 
-                let old_dependencies = &try_to_use;
+                let old_dependencies = &locked_ids;
 
                 let changes =
                     generate_dependency_changes(old_dependencies, &latest_graph.activated);
@@ -113,7 +136,7 @@ impl InstallSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+        let resolved = resolve(&manifest, &try_to_use, &package_sources, &options)?;
 
         progress.println(format!(
             "{}   Resolved {}{} dependencies",
@@ -122,6 +145,16 @@ impl InstallSubcommand {
             resolved.activated.len() - 1
         ));
 
+        for warning in duplicate_warnings(&resolved) {
+            progress.println(warning);
+        }
+
+        for warning in deprecation_warnings(&resolved) {
+            progress.println(warning);
+        }
+
+        check_licenses(&manifest, &resolved)?;
+
         let new_lockfile = Lockfile::from_resolve(&resolved);
         new_lockfile.save(&self.project_path)?;
 
@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use anyhow::bail;
+use crossterm::style::{Color, SetForegroundColor};
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
+use crate::resolution::resolve;
+
+use super::utils::disallowed_licenses;
+use super::GlobalOptions;
+
+/// List the licenses of every dependency of this project, grouped by license.
+#[derive(Debug, StructOpt)]
+pub struct LicensesSubcommand {
+    /// Path to the project to list the licenses of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+}
+
+impl LicensesSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+
+        let lockfile = Lockfile::load(&self.project_path)?
+            .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+                &manifest.package.registry,
+            )?))
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        package_sources.add_fallbacks()?;
+        package_sources.add_registries(&manifest)?;
+        package_sources.add_patches(&manifest, &self.project_path)?;
+
+        // Resolving from the lockfile lists the same versions that install
+        // would, as long as the lockfile is up to date.
+        let try_to_use: BTreeSet<PackageId> = lockfile.as_ids().collect();
+        let resolved = resolve(
+            &manifest,
+            &try_to_use,
+            &package_sources,
+            &Default::default(),
+        )?;
+
+        let root_package_id = manifest.package_id();
+        let mut by_license: BTreeMap<&str, Vec<&PackageId>> = BTreeMap::new();
+
+        for (package_id, metadata) in &resolved.metadata {
+            if *package_id != root_package_id {
+                let license = metadata.license.as_deref().unwrap_or("No license");
+                by_license.entry(license).or_default().push(package_id);
+            }
+        }
+
+        let disallowed: BTreeSet<PackageId> = disallowed_licenses(&manifest, &resolved)?
+            .into_iter()
+            .collect();
+
+        for (license, package_ids) in &by_license {
+            // Packages with the same license are either all allowed or not.
+            if disallowed.contains(package_ids[0]) {
+                println!(
+                    "{}{} {}(not allowed){}",
+                    SetForegroundColor(Color::DarkRed),
+                    license,
+                    SetForegroundColor(Color::DarkGrey),
+                    SetForegroundColor(Color::Reset)
+                );
+            } else {
+                println!(
+                    "{}{}{}",
+                    SetForegroundColor(Color::DarkGreen),
+                    license,
+                    SetForegroundColor(Color::Reset)
+                );
+            }
+
+            for package_id in package_ids {
+                println!("    {} v{}", package_id.name(), package_id.version());
+            }
+
+            println!();
+        }
+
+        if !disallowed.is_empty() {
+            bail!(
+                "{} dependencies have licenses that are not allowed by [licenses]",
+                disallowed.len()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 mod init;
 mod install;
+mod licenses;
 mod login;
 mod logout;
 mod manifest_to_json;
@@ -11,6 +12,7 @@ mod utils;
 
 pub use init::InitSubcommand;
 pub use install::InstallSubcommand;
+pub use licenses::LicensesSubcommand;
 pub use login::LoginSubcommand;
 pub use logout::LogoutSubcommand;
 pub use manifest_to_json::ManifestToJsonSubcommand;
@@ -42,6 +44,7 @@ impl Args {
             Subcommand::Search(subcommand) => subcommand.run(),
             Subcommand::Package(subcommand) => subcommand.run(),
             Subcommand::Install(subcommand) => subcommand.run(self.global),
+            Subcommand::Licenses(subcommand) => subcommand.run(self.global),
             Subcommand::ManifestToJson(subcommand) => subcommand.run(),
         }
     }
@@ -90,4 +93,5 @@ pub enum Subcommand {
     Search(SearchSubcommand),
     Package(PackageSubcommand),
     ManifestToJson(ManifestToJsonSubcommand),
+    Licenses(LicensesSubcommand),
 }
@@ -1,8 +1,9 @@
 use std::path::PathBuf;
 
+use anyhow::Context;
 use structopt::StructOpt;
 
-use crate::package_contents::PackageContents;
+use crate::{manifest::Manifest, package_contents::PackageContents};
 
 /// Package the project as a tarball suitable for uploading to a package
 /// registry.
@@ -31,6 +32,11 @@ impl PackageSubcommand {
                 println!("{}", path.display());
             }
         } else {
+            Manifest::load(&self.project_path)?
+                .package
+                .license_expression()
+                .context("Cannot package project with an invalid license.")?;
+
             let contents = PackageContents::pack_from_path(&self.project_path)?;
             fs_err::write(&self.output_path.unwrap(), contents.data())?;
         }
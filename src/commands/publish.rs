@@ -12,6 +12,9 @@ use crate::{
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The publish size limit for registries that don't advertise their own.
+const DEFAULT_MAX_PACKAGE_SIZE: u64 = 2 * 1024 * 1024;
+
 /// Publish this project to a registry.
 #[derive(Debug, StructOpt)]
 pub struct PublishSubcommand {
@@ -32,6 +35,10 @@ impl PublishSubcommand {
             bail!("Cannot publish private package.");
         }
 
+        manifest
+            .validate_metadata()
+            .context("Cannot publish package with invalid metadata.")?;
+
         let index_url = if global.test_registry {
             let index_path = Path::new(&manifest.package.registry)
                 .join("index")
@@ -48,11 +55,21 @@ impl PublishSubcommand {
             PackageIndex::new(&index_url, None)?
         };
 
-        let api = package_index.config()?.api;
+        let index_config = package_index.config()?;
+        let api = index_config.api;
+        let max_size = index_config
+            .max_package_size
+            .unwrap_or(DEFAULT_MAX_PACKAGE_SIZE)
+            .bytes();
+
         let contents = PackageContents::pack_from_path(&self.project_path)?;
 
-        if contents.data().len() > 2.mebibytes() {
-            bail!("Package size exceeds 2MB. Reduce package size and try again.");
+        if contents.data().len() > max_size {
+            bail!(
+                "Package size exceeds {} (the limit for this registry). Reduce package size and \
+                 try again.",
+                max_size
+            );
         }
 
         let auth = match self.token {
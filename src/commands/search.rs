@@ -16,8 +16,42 @@ pub struct SearchSubcommand {
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
 
-    /// The query to be dispatched to the search endpoint
+    /// The query to be dispatched to the search endpoint. Filters such as
+    /// `scope:biff` can be written directly into the query.
+    #[structopt(default_value = "")]
     pub query: String,
+
+    /// Only show packages from this scope
+    #[structopt(long = "scope")]
+    pub scope: Option<String>,
+
+    /// Only show packages for this realm (shared, server or dev)
+    #[structopt(long = "realm")]
+    pub realm: Option<String>,
+
+    /// Only show packages with this license
+    #[structopt(long = "license")]
+    pub license: Option<String>,
+
+    /// Only show packages by this author
+    #[structopt(long = "author")]
+    pub author: Option<String>,
+
+    /// Only show packages in this category
+    #[structopt(long = "category")]
+    pub category: Option<String>,
+
+    /// How to order results: relevance, downloads, updated or name
+    #[structopt(long = "sort", default_value = "relevance")]
+    pub sort: String,
+
+    /// Number of results to skip
+    #[structopt(long = "offset", default_value = "0")]
+    pub offset: usize,
+
+    /// Maximum number of results to show
+    #[structopt(long = "limit", default_value = "100")]
+    pub limit: usize,
 }
 
 impl SearchSubcommand {
@@ -30,10 +64,28 @@ impl SearchSubcommand {
 
         let auth = auth_store.tokens.get(api.as_str());
 
+        let mut query = self.query.clone();
+        let filters = [
+            ("scope", &self.scope),
+            ("realm", &self.realm),
+            ("license", &self.license),
+            ("author", &self.author),
+            ("category", &self.category),
+        ];
+
+        for (key, value) in filters.iter() {
+            if let Some(value) = value {
+                query.push_str(&format!(" {}:{}", key, value));
+            }
+        }
+
         let client = Client::new();
-        let mut request = client
-            .get(api.join("/v1/package-search/")?)
-            .query(&[("query", &self.query)]);
+        let mut request = client.get(api.join("/v1/package-search/")?).query(&[
+            ("query", query),
+            ("sort", self.sort.clone()),
+            ("offset", self.offset.to_string()),
+            ("limit", self.limit.to_string()),
+        ]);
 
         if let Some(auth) = auth {
             request = request.header(AUTHORIZATION, format!("Bearer {}", auth));
@@ -49,6 +101,13 @@ impl SearchSubcommand {
             );
         }
 
+        // Older registries don't report how many packages matched in total.
+        let total: Option<usize> = response
+            .headers()
+            .get("Wally-Total-Count")
+            .and_then(|total| total.to_str().ok())
+            .and_then(|total| total.parse().ok());
+
         let mut results: Vec<SearchResult> = response.json()?;
         println!();
 
@@ -72,12 +131,68 @@ impl SearchSubcommand {
 
             println!("{}", SetForegroundColor(Color::Reset));
 
+            let tags: Vec<&String> = result.keywords.iter().chain(&result.categories).collect();
+            let has_details = result.description.is_some()
+                || !tags.is_empty()
+                || result.documentation.is_some()
+                || result.deprecated.is_some();
+
+            if let Some(deprecated) = &result.deprecated {
+                let replacement = match &result.replacement {
+                    Some(replacement) => format!(" (use {} instead)", replacement),
+                    None => String::new(),
+                };
+
+                println!(
+                    "    {}Deprecated: {}{}{}",
+                    SetForegroundColor(Color::Yellow),
+                    SetForegroundColor(Color::Reset),
+                    deprecated,
+                    replacement
+                );
+            }
+
             if let Some(description) = &result.description {
                 println!("    {}", description);
+            }
+
+            if !tags.is_empty() {
+                let tags: Vec<String> = tags.iter().map(|tag| format!("#{}", tag)).collect();
+                println!(
+                    "    {}{}{}",
+                    SetForegroundColor(Color::DarkCyan),
+                    tags.join(" "),
+                    SetForegroundColor(Color::Reset)
+                );
+            }
+
+            if let Some(documentation) = &result.documentation {
+                println!(
+                    "    {}Docs: {}{}",
+                    SetForegroundColor(Color::DarkGrey),
+                    SetForegroundColor(Color::Reset),
+                    documentation
+                );
+            }
+
+            if has_details {
                 println!();
             }
         }
 
+        if let Some(total) = total {
+            if results.is_empty() {
+                println!("No packages found");
+            } else {
+                println!(
+                    "Showing {}-{} of {} packages",
+                    self.offset + 1,
+                    self.offset + results.len(),
+                    total
+                );
+            }
+        }
+
         println!();
 
         Ok(())
@@ -90,4 +205,16 @@ struct SearchResult {
     pub name: String,
     pub versions: Vec<String>,
     pub description: Option<String>,
+
+    // Older registries don't return these.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub documentation: Option<String>,
+    #[serde(default)]
+    pub deprecated: Option<String>,
+    #[serde(default)]
+    pub replacement: Option<String>,
 }
@@ -11,12 +11,16 @@ use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::{resolution, GlobalOptions};
+use crate::resolution::{self, ResolveOptions};
+use crate::GlobalOptions;
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
-use super::utils::{generate_dependency_changes, render_update_difference};
+use super::utils::{
+    check_licenses, deprecation_warnings, duplicate_warnings, generate_dependency_changes,
+    render_update_difference,
+};
 
 /// Update all of the dependencies of this project.
 #[derive(Debug, StructOpt)]
@@ -25,6 +29,17 @@ pub struct UpdateSubcommand {
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
 
+    /// Allow updating to prereleases of versions that dependency requirements
+    /// match. Otherwise, prereleases are only picked when a requirement names
+    /// one.
+    #[structopt(long = "pre")]
+    pub pre: bool,
+
+    /// Update to the lowest versions that satisfy each requirement instead of
+    /// the highest, to check that the lower bounds of requirements still work.
+    #[structopt(long = "minimal-versions")]
+    pub minimal_versions: bool,
+
     /// An optional list of dependencies to update.
     /// They must be valid package name with an optional version requirement.
     pub package_specs: Vec<PackageSpec>,
@@ -51,6 +66,8 @@ impl UpdateSubcommand {
 
         let mut package_sources = PackageSourceMap::new(default_registry);
         package_sources.add_fallbacks()?;
+        package_sources.add_registries(&manifest)?;
+        package_sources.add_patches(&manifest, &self.project_path)?;
 
         // If the user didn't specify any targets, then update all of the packages.
         // Otherwise, find the target packages to update.
@@ -89,7 +106,12 @@ impl UpdateSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-        let resolved_graph = resolution::resolve(&manifest, &try_to_use, &package_sources)?;
+        let options = ResolveOptions {
+            prerelease: self.pre,
+            minimal_versions: self.minimal_versions,
+        };
+        let resolved_graph =
+            resolution::resolve(&manifest, &try_to_use, &package_sources, &options)?;
 
         progress.println(format!(
             "{}   Resolved {}{} total dependencies",
@@ -98,6 +120,16 @@ impl UpdateSubcommand {
             resolved_graph.activated.len() - 1
         ));
 
+        for warning in duplicate_warnings(&resolved_graph) {
+            progress.println(warning);
+        }
+
+        for warning in deprecation_warnings(&resolved_graph) {
+            progress.println(warning);
+        }
+
+        check_licenses(&manifest, &resolved_graph)?;
+
         progress.enable_steady_tick(Duration::from_millis(100));
         progress.suspend(|| {
             let dependency_changes = generate_dependency_changes(
@@ -1,4 +1,7 @@
-use crate::{package_id::PackageId, package_name::PackageName};
+use crate::{
+    manifest::Manifest, package_id::PackageId, package_name::PackageName, resolution::Resolve,
+};
+use anyhow::bail;
 use crossterm::style::{Color, SetForegroundColor};
 use serde::Serialize;
 use std::{collections::BTreeSet, io::Write};
@@ -11,6 +14,19 @@ pub(crate) enum DependencyChange {
     Downgraded { from: PackageId, to: PackageId },
 }
 
+impl DependencyChange {
+    /// Whether this change leaves a prerelease version installed.
+    pub(crate) fn is_prerelease(&self) -> bool {
+        match self {
+            DependencyChange::Added(package_id) => package_id.version().is_prerelease(),
+            DependencyChange::Removed(_) => false,
+            DependencyChange::Upgraded { to, .. } | DependencyChange::Downgraded { to, .. } => {
+                to.version().is_prerelease()
+            }
+        }
+    }
+}
+
 pub(crate) fn generate_dependency_changes(
     old_dependencies: &BTreeSet<PackageId>,
     new_dependencies: &BTreeSet<PackageId>,
@@ -77,53 +93,177 @@ pub(crate) fn render_update_difference(
         return Ok(());
     }
 
-    writeln!(
-        writer,
-        "{} Dependency changes{}",
-        SetForegroundColor(Color::DarkGreen),
-        SetForegroundColor(Color::Reset)
-    )?;
-
-    for dependency_change in dependency_changes {
-        match dependency_change {
-            DependencyChange::Added(package_id) => writeln!(
-                writer,
-                "{}      Added {}{} v{}",
-                SetForegroundColor(Color::DarkGreen),
-                SetForegroundColor(Color::Reset),
-                package_id.name(),
-                package_id.version()
-            ),
-            DependencyChange::Removed(package_id) => writeln!(
-                writer,
-                "{}    Removed {}{} v{}",
-                SetForegroundColor(Color::DarkRed),
-                SetForegroundColor(Color::Reset),
-                package_id.name(),
-                package_id.version()
-            ),
-            DependencyChange::Upgraded { from, to } => writeln!(
-                writer,
-                "{}    Updated {}{} from v{} to v{}",
-                SetForegroundColor(Color::DarkCyan),
+    // Prereleases are listed on their own so that they stand out from the
+    // stable versions that most changes are to.
+    let (prerelease_changes, stable_changes): (Vec<_>, Vec<_>) = dependency_changes
+        .iter()
+        .partition(|dependency_change| dependency_change.is_prerelease());
+
+    if !stable_changes.is_empty() {
+        writeln!(
+            writer,
+            "{} Dependency changes{}",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset)
+        )?;
+
+        for dependency_change in stable_changes {
+            render_dependency_change(dependency_change, writer)?;
+        }
+    }
+
+    if !prerelease_changes.is_empty() {
+        writeln!(
+            writer,
+            "{} Prerelease changes{}",
+            SetForegroundColor(Color::DarkYellow),
+            SetForegroundColor(Color::Reset)
+        )?;
+
+        for dependency_change in prerelease_changes {
+            render_dependency_change(dependency_change, writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn render_dependency_change(
+    dependency_change: &DependencyChange,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    match dependency_change {
+        DependencyChange::Added(package_id) => writeln!(
+            writer,
+            "{}      Added {}{} v{}",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset),
+            package_id.name(),
+            package_id.version()
+        ),
+        DependencyChange::Removed(package_id) => writeln!(
+            writer,
+            "{}    Removed {}{} v{}",
+            SetForegroundColor(Color::DarkRed),
+            SetForegroundColor(Color::Reset),
+            package_id.name(),
+            package_id.version()
+        ),
+        DependencyChange::Upgraded { from, to } => writeln!(
+            writer,
+            "{}    Updated {}{} from v{} to v{}",
+            SetForegroundColor(Color::DarkCyan),
+            SetForegroundColor(Color::Reset),
+            from.name(),
+            from.version(),
+            to.version()
+        ),
+        DependencyChange::Downgraded { from, to } => writeln!(
+            writer,
+            "{} Downgraded {}{} from v{} to v{}",
+            SetForegroundColor(Color::DarkYellow),
+            SetForegroundColor(Color::Reset),
+            from.name(),
+            from.version(),
+            to.version()
+        ),
+    }?;
+
+    Ok(())
+}
+
+/// Warnings for every package that will be installed more than once, because
+/// it's required at SemVer incompatible versions.
+pub(crate) fn duplicate_warnings(resolve: &Resolve) -> Vec<String> {
+    resolve
+        .duplicates()
+        .into_iter()
+        .map(|(name, versions)| {
+            let versions: Vec<_> = versions
+                .iter()
+                .map(|version| format!("v{}", version))
+                .collect();
+
+            format!(
+                "{}    Warning {}multiple versions of {} will be installed: {}",
+                SetForegroundColor(Color::Yellow),
                 SetForegroundColor(Color::Reset),
-                from.name(),
-                from.version(),
-                to.version()
-            ),
-            DependencyChange::Downgraded { from, to } => writeln!(
-                writer,
-                "{} Downgraded {}{} from v{} to v{}",
-                SetForegroundColor(Color::DarkYellow),
+                name,
+                versions.join(", ")
+            )
+        })
+        .collect()
+}
+
+/// Warnings for every package in the graph that its owners have deprecated.
+pub(crate) fn deprecation_warnings(resolve: &Resolve) -> Vec<String> {
+    resolve
+        .deprecated
+        .iter()
+        .map(|(package_id, deprecation)| {
+            let replacement = match &deprecation.replacement {
+                Some(replacement) => format!(" (use {} instead)", replacement),
+                None => String::new(),
+            };
+
+            format!(
+                "{} Deprecated {}{}: {}{}",
+                SetForegroundColor(Color::Yellow),
                 SetForegroundColor(Color::Reset),
-                from.name(),
-                from.version(),
-                to.version()
-            ),
-        }?
+                package_id,
+                deprecation.message,
+                replacement
+            )
+        })
+        .collect()
+}
+
+/// Every dependency in the graph with a license that the root manifest's
+/// `[licenses]` section doesn't allow.
+pub(crate) fn disallowed_licenses(
+    manifest: &Manifest,
+    resolve: &Resolve,
+) -> anyhow::Result<Vec<PackageId>> {
+    let root_package_id = manifest.package_id();
+    let mut disallowed = Vec::new();
+
+    if manifest.licenses.is_empty() {
+        return Ok(disallowed);
     }
 
-    Ok(())
+    for (package_id, metadata) in &resolve.metadata {
+        if *package_id != root_package_id
+            && !manifest.licenses.allows(metadata.license.as_deref())?
+        {
+            disallowed.push(package_id.clone());
+        }
+    }
+
+    Ok(disallowed)
+}
+
+/// Fail if any dependency in the graph has a license that the root manifest's
+/// `[licenses]` section doesn't allow.
+pub(crate) fn check_licenses(manifest: &Manifest, resolve: &Resolve) -> anyhow::Result<()> {
+    let disallowed = disallowed_licenses(manifest, resolve)?;
+
+    if disallowed.is_empty() {
+        return Ok(());
+    }
+
+    let packages: Vec<String> = disallowed
+        .iter()
+        .map(|package_id| {
+            let license = resolve.metadata[package_id].license.as_deref();
+            format!("    {} ({})", package_id, license.unwrap_or("no license"))
+        })
+        .collect();
+
+    bail!(
+        "These dependencies have licenses that are not allowed by [licenses]:\n{}\n\
+         Run wally licenses to see the licenses of every dependency.",
+        packages.join("\n")
+    );
 }
 
 #[cfg(test)]
@@ -275,6 +415,34 @@ mod test {
         )
     }
 
+    #[test]
+    fn render_prerelease_changes_separately() {
+        let changes = generate_dependency_changes(
+            &BTreeSet::from([
+                package_id!("biff/stable@1.0.0"),
+                package_id!("biff/unstable@1.0.0"),
+            ]),
+            &BTreeSet::from([
+                package_id!("biff/stable@1.1.0"),
+                package_id!("biff/unstable@1.1.0-beta.1"),
+            ]),
+        );
+
+        let mut writer = Vec::new();
+        render_update_difference(&changes, &mut writer).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+
+        let stable = output.find("Dependency changes").unwrap();
+        let stable_change = output.find("biff/stable from v1.0.0 to v1.1.0").unwrap();
+        let prerelease = output.find("Prerelease changes").unwrap();
+        let prerelease_change = output
+            .find("biff/unstable from v1.0.0 to v1.1.0-beta.1")
+            .unwrap();
+
+        assert!(stable < stable_change && stable_change < prerelease);
+        assert!(prerelease < prerelease_change);
+    }
+
     #[test]
     fn snapshot_output_when_no_changes() {
         let changes = Vec::new();
@@ -219,3 +219,42 @@ pub fn update_index(access_token: Option<String>, repository: &Repository) -> an
 
     Ok(())
 }
+
+/// Check out the given branch, tag or commit in a freshly cloned repository,
+/// leaving HEAD detached.
+pub fn checkout(repository: &Repository, rev: &str) -> anyhow::Result<()> {
+    // Only the default branch exists locally after a clone, so other branches
+    // have to be found through the remote.
+    let object = repository
+        .revparse_single(rev)
+        .or_else(|_| repository.revparse_single(&format!("origin/{}", rev)))
+        .with_context(|| format!("could not find revision {}", rev))?;
+    let commit = object.peel_to_commit()?;
+
+    let mut options = git2::build::CheckoutBuilder::new();
+    options.force();
+
+    repository.checkout_tree(commit.as_object(), Some(&mut options))?;
+    repository.set_head_detached(commit.id())?;
+
+    Ok(())
+}
+
+/// Throw away any commits and changes to tracked files made since the given
+/// commit, without touching the remote.
+pub fn reset_hard(repository: &Repository, commit: git2::Oid) -> anyhow::Result<()> {
+    let commit = repository.find_commit(commit)?;
+
+    let mut options = git2::build::CheckoutBuilder::new();
+    options.force();
+
+    repository
+        .reset(
+            commit.as_object(),
+            git2::ResetType::Hard,
+            Some(&mut options),
+        )
+        .with_context(|| format!("could not reset git repo to {}", commit.id()))?;
+
+    Ok(())
+}
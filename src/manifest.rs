@@ -1,8 +1,10 @@
-use std::collections::BTreeMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::Context;
-use semver::Version;
+use anyhow::{bail, Context};
+use semver::{Version, VersionReq};
+use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
 
 use crate::package_id::PackageId;
@@ -11,6 +13,16 @@ use crate::package_req::PackageReq;
 
 pub const MANIFEST_FILE_NAME: &str = "wally.toml";
 
+const WALLY_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Limits on the metadata that registries show for a package, checked before
+/// it's published.
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+const MAX_KEYWORDS: usize = 5;
+const MAX_KEYWORD_LENGTH: usize = 20;
+const MAX_CATEGORIES: usize = 5;
+const MAX_CATEGORY_LENGTH: usize = 50;
+
 /// The contents of a `wally.toml` file, which defines a package.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -20,14 +32,35 @@ pub struct Manifest {
     #[serde(default)]
     pub place: PlaceInfo,
 
-    #[serde(default)]
-    pub dependencies: BTreeMap<String, PackageReq>,
+    #[serde(default, serialize_with = "toml::ser::tables_last")]
+    pub dependencies: BTreeMap<String, Dependency>,
 
-    #[serde(default)]
-    pub server_dependencies: BTreeMap<String, PackageReq>,
+    #[serde(default, serialize_with = "toml::ser::tables_last")]
+    pub server_dependencies: BTreeMap<String, Dependency>,
 
-    #[serde(default)]
-    pub dev_dependencies: BTreeMap<String, PackageReq>,
+    #[serde(default, serialize_with = "toml::ser::tables_last")]
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+
+    /// Named sets of optional dependencies that consumers of this package can
+    /// turn on. Each feature lists optional dependency aliases and other
+    /// features that it enables.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, Vec<String>>,
+
+    /// Names for registries that dependencies can be pulled from instead of
+    /// `package.registry`, mapped to the URL of the registry's index.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub registries: BTreeMap<String, String>,
+
+    /// Replacements for packages anywhere in the dependency graph. Patches
+    /// are only applied from the root package's manifest.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub patch: BTreeMap<PackageName, Patch>,
+
+    /// Licenses that the packages in the dependency graph may use. Like
+    /// patches, this is only read from the root package's manifest.
+    #[serde(default, skip_serializing_if = "LicensePolicy::is_empty")]
+    pub licenses: LicensePolicy,
 }
 
 impl Manifest {
@@ -36,6 +69,9 @@ impl Manifest {
         let file_path = dir.join(MANIFEST_FILE_NAME);
 
         let content = fs_err::read_to_string(&file_path)?;
+        check_compatible(&content)
+            .with_context(|| format!("cannot use manifest at path {}", file_path.display()))?;
+
         let manifest: Manifest = toml::from_str(&content)
             .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
 
@@ -52,6 +88,285 @@ impl Manifest {
     pub fn package_id(&self) -> PackageId {
         PackageId::new(self.package.name.clone(), self.package.version.clone())
     }
+
+    /// Expand a list of requested features into everything they enable,
+    /// following features that enable other features. Optional dependencies
+    /// can be requested by alias as if they were features.
+    pub fn expand_features<'a, I>(&self, requested: I) -> anyhow::Result<BTreeSet<String>>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let mut enabled = BTreeSet::new();
+        let mut to_visit: Vec<&String> = requested.into_iter().collect();
+
+        while let Some(name) = to_visit.pop() {
+            if enabled.contains(name) {
+                continue;
+            }
+
+            if let Some(enables) = self.features.get(name) {
+                to_visit.extend(enables);
+            } else if !self.has_optional_dependency(name) {
+                bail!(
+                    "Package {} has no feature or optional dependency named {}",
+                    self.package.name,
+                    name
+                );
+            }
+
+            enabled.insert(name.clone());
+        }
+
+        Ok(enabled)
+    }
+
+    /// Check the metadata that registries show for this package, so that
+    /// mistakes are caught before it's published.
+    pub fn validate_metadata(&self) -> anyhow::Result<()> {
+        let package = &self.package;
+
+        if let Some(description) = &package.description {
+            if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+                bail!(
+                    "description is longer than {} characters",
+                    MAX_DESCRIPTION_LENGTH
+                );
+            }
+        }
+
+        if package.keywords.len() > MAX_KEYWORDS {
+            bail!("packages can have at most {} keywords", MAX_KEYWORDS);
+        }
+
+        for keyword in &package.keywords {
+            if keyword.is_empty() || keyword.chars().count() > MAX_KEYWORD_LENGTH {
+                bail!(
+                    "keyword '{}' must be between 1 and {} characters long",
+                    keyword,
+                    MAX_KEYWORD_LENGTH
+                );
+            }
+        }
+
+        if package.categories.len() > MAX_CATEGORIES {
+            bail!("packages can have at most {} categories", MAX_CATEGORIES);
+        }
+
+        for category in &package.categories {
+            let valid_characters = category
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+            if category.is_empty() || category.len() > MAX_CATEGORY_LENGTH || !valid_characters {
+                bail!(
+                    "category '{}' must be between 1 and {} lowercase letters, digits or dashes",
+                    category,
+                    MAX_CATEGORY_LENGTH
+                );
+            }
+        }
+
+        package.license_expression()?;
+
+        if let Some(documentation) = &package.documentation {
+            url::Url::parse(documentation)
+                .with_context(|| format!("documentation '{}' is not a valid URL", documentation))?;
+        }
+
+        if let Some(readme) = &package.readme {
+            let inside_package = readme
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+            if !inside_package {
+                bail!(
+                    "readme '{}' must be a path inside the package, like README.md",
+                    readme.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turn the `registry` of one of this manifest's dependencies into a
+    /// registry URL, looking up names defined in `[registries]`.
+    pub fn registry_spec<'a>(&'a self, registry: &'a str) -> &'a str {
+        self.registries
+            .get(registry)
+            .map(String::as_str)
+            .unwrap_or(registry)
+    }
+
+    fn has_optional_dependency(&self, alias: &str) -> bool {
+        [
+            &self.dependencies,
+            &self.server_dependencies,
+            &self.dev_dependencies,
+        ]
+        .iter()
+        .any(|dependencies| {
+            dependencies
+                .get(alias)
+                .map_or(false, |dependency| dependency.is_optional())
+        })
+    }
+}
+
+/// Check that this version of Wally can work with a manifest, before parsing
+/// the rest of it with rules that may have changed since. Manifests that can't
+/// be parsed at all are left for the full parse to report on.
+fn check_compatible(content: &str) -> anyhow::Result<()> {
+    let package = match toml::from_str::<toml::Value>(content) {
+        Ok(toml::Value::Table(mut table)) => match table.remove("package") {
+            Some(package) => package,
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+
+    let wally_version = match package.get("wally-version").and_then(toml::Value::as_str) {
+        Some(required) => Some(
+            Version::parse(required)
+                .with_context(|| format!("wally-version {} is not a valid version", required))?,
+        ),
+        None => None,
+    };
+    let edition = package.get("edition").and_then(toml::Value::as_str);
+
+    check_requirements(wally_version.as_ref(), edition)
+}
+
+/// Check a package's `wally-version` and `edition` against this version of
+/// Wally.
+fn check_requirements(
+    wally_version: Option<&Version>,
+    edition: Option<&str>,
+) -> anyhow::Result<()> {
+    let current = Version::parse(WALLY_VERSION).unwrap();
+
+    if let Some(required) = wally_version {
+        if &current < required {
+            bail!(
+                "This package needs Wally {} or newer, but this is Wally {}.\n\
+                 Upgrade Wally to work on this package.",
+                required,
+                current
+            );
+        }
+    }
+
+    if let Some(edition) = edition {
+        if !Edition::KNOWN.contains(&edition) {
+            bail!(
+                "This package uses manifest edition {}, which Wally {} does not know about.\n\
+                 Upgrade Wally to work on this package.",
+                edition,
+                current
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// A dependency on another package. Most dependencies are just a package
+/// requirement, like `"roblox/roact@1.4.2"`, but a table can be used to give
+/// more details.
+///
+/// Example: `{ version = "roblox/roact@1.4.2", features = ["hooks"] }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Dependency {
+    Simple(PackageReq),
+    Detailed(DetailedDependency),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DetailedDependency {
+    /// The package and versions to depend on.
+    pub version: PackageReq,
+
+    /// Whether this dependency is only used when one of the package's
+    /// features turns it on.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+
+    /// Features of the dependency to turn on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+
+    /// The registry to pull this dependency from instead of the package's
+    /// registry and its fallbacks. Either a URL or a name from `[registries]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+}
+
+impl Dependency {
+    pub fn req(&self) -> &PackageReq {
+        match self {
+            Dependency::Simple(req) => req,
+            Dependency::Detailed(detailed) => &detailed.version,
+        }
+    }
+
+    pub fn is_optional(&self) -> bool {
+        match self {
+            Dependency::Simple(_) => false,
+            Dependency::Detailed(detailed) => detailed.optional,
+        }
+    }
+
+    pub fn features(&self) -> &[String] {
+        match self {
+            Dependency::Simple(_) => &[],
+            Dependency::Detailed(detailed) => &detailed.features,
+        }
+    }
+
+    pub fn registry(&self) -> Option<&str> {
+        match self {
+            Dependency::Simple(_) => None,
+            Dependency::Detailed(detailed) => detailed.registry.as_deref(),
+        }
+    }
+}
+
+impl From<PackageReq> for Dependency {
+    fn from(req: PackageReq) -> Self {
+        Dependency::Simple(req)
+    }
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Written by hand instead of using an untagged enum, so that mistakes
+        // in either form get a useful error message.
+        deserializer.deserialize_any(DependencyVisitor)
+    }
+}
+
+struct DependencyVisitor;
+
+impl<'de> Visitor<'de> for DependencyVisitor {
+    type Value = Dependency;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "a package requirement of the form SCOPE/NAME@VERSION_REQ or a dependency table"
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value.parse().map(Dependency::Simple).map_err(E::custom)
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Self::Value, M::Error> {
+        DetailedDependency::deserialize(de::value::MapAccessDeserializer::new(map))
+            .map(Dependency::Detailed)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,7 +400,8 @@ pub struct Package {
     /// Example: `A game about adopting things.`
     pub description: Option<String>,
 
-    /// An SPDX license specifier for the package.
+    /// An SPDX license expression for the package, checked before the package
+    /// is published.
     ///
     /// Example: `MIT OR Apache-2.0`
     pub license: Option<String>,
@@ -96,6 +412,27 @@ pub struct Package {
     #[serde(default)]
     pub authors: Vec<String>,
 
+    /// A list of keywords that help people find the package when searching a
+    /// registry.
+    ///
+    /// Example: ["ui", "animation"]
+    #[serde(default)]
+    pub keywords: Vec<String>,
+
+    /// Categories from the registry that the package belongs in, written in
+    /// lowercase with dashes.
+    ///
+    /// Example: ["user-interface", "networking"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+
+    /// The package's README, relative to the manifest. It's always included in
+    /// the package so that registries can show it.
+    ///
+    /// Example: "README.md"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readme: Option<PathBuf>,
+
     /// A list of paths to include in the package. Glob patterns are supported.
     ///
     /// By default all directories and files are included except files generated
@@ -138,6 +475,112 @@ pub struct Package {
     /// Example: "https://github.com/Sleitnick/Knit.git"
     #[serde(default)]
     pub repository: Option<String>,
+
+    /// URL of the package documentation.
+    ///
+    /// Example: "https://sleitnick.github.io/Knit/"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<String>,
+
+    /// The oldest version of Wally that can work on the package. Older
+    /// versions refuse to load its manifest.
+    ///
+    /// Example: `0.4.0`
+    #[serde(
+        default,
+        rename = "wally-version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wally_version: Option<Version>,
+
+    /// The edition of the manifest format that the package is written for.
+    /// Editions let the meaning of manifests change without changing what
+    /// existing packages mean.
+    ///
+    /// Example: `2023`
+    #[serde(default, skip_serializing_if = "Edition::is_default")]
+    pub edition: Edition,
+}
+
+impl Package {
+    /// Check that this version of Wally can work with the package. Manifests
+    /// we load ourselves are checked before they're parsed, but packages from
+    /// registries are only checked once they're picked as a dependency.
+    pub fn check_compatible(&self) -> anyhow::Result<()> {
+        check_requirements(self.wally_version.as_ref(), Some(self.edition.as_str()))
+    }
+
+    /// Parse the package's license as an SPDX license expression.
+    pub fn license_expression(&self) -> anyhow::Result<Option<spdx::Expression>> {
+        let license = match &self.license {
+            Some(license) => license,
+            None => return Ok(None),
+        };
+
+        let expression = spdx::Expression::parse(license).with_context(|| {
+            format!(
+                "license '{}' is not a valid SPDX license expression",
+                license
+            )
+        })?;
+
+        Ok(Some(expression))
+    }
+}
+
+/// Editions of the manifest format. Manifests without an edition use the
+/// first one, which is how manifests worked before editions existed.
+///
+/// Editions are kept as strings so that packages in an index written for
+/// editions we don't know about yet can still be read, and then turned away
+/// with a helpful message by [`Package::check_compatible`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Edition(String);
+
+impl Edition {
+    /// Every edition that this version of Wally understands.
+    const KNOWN: &'static [&'static str] = &["2023"];
+
+    pub fn new<S: Into<String>>(edition: S) -> Self {
+        Self(edition.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Edition::default()
+    }
+}
+
+impl Default for Edition {
+    fn default() -> Self {
+        Edition(String::from(Self::KNOWN[0]))
+    }
+}
+
+/// What a package should be replaced with wherever it appears in the
+/// dependency graph, defined in the `[patch]` section of a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Patch {
+    /// Use a package from a directory on disk, relative to the project.
+    ///
+    /// Example: `{ path = "../roact-fork" }`
+    Path { path: PathBuf },
+
+    /// Use a package from a Git repository, optionally at a specific branch,
+    /// tag or commit.
+    ///
+    /// Example: `{ git = "https://github.com/biff/roact.git", rev = "fix-bug" }`
+    Git { git: String, rev: Option<String> },
+
+    /// Use a different version of the package from its registry.
+    ///
+    /// Example: `{ version = "=1.4.2" }`
+    Version { version: VersionReq },
 }
 
 // Metadata we require when this manifest will be used to generate package folders
@@ -158,6 +601,70 @@ pub struct PlaceInfo {
     pub server_packages: Option<String>,
 }
 
+/// Licenses that a project allows its dependencies to use, given in the
+/// `[licenses]` section of the root manifest and checked on install.
+///
+/// Entries are SPDX license identifiers, optionally with an exception, like
+/// `Apache-2.0 WITH LLVM-exception`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LicensePolicy {
+    /// If any licenses are listed, every dependency has to be usable under
+    /// one of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    /// Licenses that dependencies can never be used under.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl LicensePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether a package with the given license can be used under this
+    /// policy. A license expression like `MIT OR GPL-3.0` is allowed if any of
+    /// the choices it offers are.
+    ///
+    /// Packages published before licenses were checked may not have a valid
+    /// license, which is only accepted when there's no allow list.
+    pub fn allows(&self, license: Option<&str>) -> anyhow::Result<bool> {
+        let allow = parse_licensees(&self.allow)?;
+        let deny = parse_licensees(&self.deny)?;
+
+        let expression = license
+            .and_then(|license| spdx::Expression::parse_mode(license, spdx::ParseMode::LAX).ok());
+
+        let expression = match expression {
+            Some(expression) => expression,
+            None => return Ok(allow.is_empty()),
+        };
+
+        Ok(expression.evaluate(|req| {
+            let allowed = allow.is_empty() || allow.iter().any(|licensee| licensee.satisfies(req));
+            let denied = deny.iter().any(|licensee| licensee.satisfies(req));
+
+            allowed && !denied
+        }))
+    }
+}
+
+fn parse_licensees(licenses: &[String]) -> anyhow::Result<Vec<spdx::Licensee>> {
+    licenses
+        .iter()
+        .map(|license| {
+            spdx::Licensee::parse(license).with_context(|| {
+                format!(
+                    "'{}' in [licenses] is not a valid SPDX license identifier",
+                    license
+                )
+            })
+        })
+        .collect()
+}
+
 impl Default for PlaceInfo {
     fn default() -> Self {
         Self {
@@ -185,3 +692,117 @@ impl Realm {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "biff/minimal"
+        version = "0.1.0"
+        registry = "test-registries/primary-registry"
+        realm = "shared"
+    "#;
+
+    #[test]
+    fn compatible() {
+        check_compatible(MANIFEST).unwrap();
+
+        let manifest = format!(
+            "{}wally-version = \"0.1.0\"\nedition = \"2023\"\n",
+            MANIFEST
+        );
+        check_compatible(&manifest).unwrap();
+
+        let manifest: Manifest = toml::from_str(&manifest).unwrap();
+        assert_eq!(manifest.package.wally_version, Some(Version::new(0, 1, 0)));
+        assert_eq!(manifest.package.edition, Edition::new("2023"));
+        manifest.package.check_compatible().unwrap();
+    }
+
+    #[test]
+    fn newer_wally_version() {
+        let manifest = format!("{}wally-version = \"999.0.0\"\n", MANIFEST);
+        let err = check_compatible(&manifest).unwrap_err();
+
+        assert!(err.to_string().contains("needs Wally 999.0.0 or newer"));
+    }
+
+    #[test]
+    fn unknown_edition() {
+        let manifest = format!("{}edition = \"2099\"\n", MANIFEST);
+        let err = check_compatible(&manifest).unwrap_err();
+
+        assert!(err.to_string().contains("edition 2099"));
+
+        // Manifests from a registry still parse, so that other versions of the
+        // package can be used.
+        let manifest: Manifest = toml::from_str(&manifest).unwrap();
+        let err = manifest.package.check_compatible().unwrap_err();
+
+        assert!(err.to_string().contains("edition 2099"));
+    }
+
+    #[test]
+    fn validate_metadata() {
+        let valid = format!(
+            "{}license = \"MIT OR Apache-2.0 WITH LLVM-exception\"\n\
+             keywords = [\"ui\"]\n\
+             categories = [\"user-interface\"]\n\
+             readme = \"docs/README.md\"\n",
+            MANIFEST
+        );
+        let manifest: Manifest = toml::from_str(&valid).unwrap();
+        manifest.validate_metadata().unwrap();
+
+        let invalid = [
+            "keywords = [\"a\", \"b\", \"c\", \"d\", \"e\", \"f\"]",
+            "keywords = [\"much-too-long-to-be-a-keyword\"]",
+            "categories = [\"User Interface\"]",
+            "documentation = \"not a url\"",
+            "readme = \"../README.md\"",
+            "license = \"MIT OR NOPE\"",
+            "license = \"MIT AND\"",
+        ];
+
+        for field in invalid.iter() {
+            let manifest: Manifest = toml::from_str(&format!("{}{}\n", MANIFEST, field)).unwrap();
+            assert!(
+                manifest.validate_metadata().is_err(),
+                "{} was allowed",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn license_policy() {
+        let policy = LicensePolicy {
+            allow: vec![String::from("MIT"), String::from("Apache-2.0")],
+            deny: vec![String::from("Apache-2.0")],
+        };
+
+        assert!(policy.allows(Some("MIT")).unwrap());
+        assert!(policy.allows(Some("MIT OR GPL-3.0-only")).unwrap());
+        assert!(!policy.allows(Some("MIT AND GPL-3.0-only")).unwrap());
+        assert!(!policy.allows(Some("Apache-2.0")).unwrap());
+        assert!(!policy.allows(None).unwrap());
+
+        let deny_only = LicensePolicy {
+            allow: Vec::new(),
+            deny: vec![String::from("GPL-3.0")],
+        };
+
+        assert!(deny_only.allows(Some("MIT")).unwrap());
+        assert!(deny_only.allows(None).unwrap());
+        assert!(!deny_only.allows(Some("GPL-3.0-or-later")).unwrap());
+
+        let invalid = LicensePolicy {
+            allow: vec![String::from("NOPE")],
+            deny: Vec::new(),
+        };
+
+        assert!(invalid.allows(Some("MIT")).is_err());
+    }
+}
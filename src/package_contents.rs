@@ -1,7 +1,7 @@
 use std::io::{self, BufRead, BufReader, Cursor};
 use std::path::{Path, PathBuf};
 
-use anyhow::format_err;
+use anyhow::{bail, format_err};
 use fs_err::File;
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde_json::json;
@@ -33,7 +33,23 @@ impl PackageContents {
         let mut data = Vec::new();
         let mut archive = ZipWriter::new(Cursor::new(&mut data));
 
-        for path in Self::filtered_contents(input)? {
+        let mut paths = Self::filtered_contents(input)?;
+
+        // The readme is always packaged, even if include or exclude would
+        // leave it out, so that registries can show it.
+        if let Some(readme) = &manifest.package.readme {
+            let readme_path = input.join(readme);
+
+            if !readme_path.is_file() {
+                bail!("readme {} does not exist", readme.display());
+            }
+
+            if !paths.contains(&readme_path) {
+                paths.push(readme_path);
+            }
+        }
+
+        for path in paths {
             let relative_path = path.strip_prefix(input).unwrap();
             let archive_name = relative_path.to_str().ok_or_else(|| {
                 format_err!(
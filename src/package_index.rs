@@ -1,11 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{BufReader, ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
 use fs_err::{create_dir_all, File, OpenOptions};
 use git2::Repository;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use url::Url;
@@ -14,6 +15,9 @@ use crate::git_util;
 use crate::manifest::Manifest;
 use crate::package_name::PackageName;
 
+/// How many times to try pushing a change to the index before giving up.
+const PUBLISH_ATTEMPTS: usize = 5;
+
 /// Configuration contained in the index's `config.json` file.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageIndexConfig {
@@ -22,6 +26,11 @@ pub struct PackageIndexConfig {
 
     #[serde(default)]
     pub fallback_registries: Vec<String>,
+
+    /// The largest package archive, in bytes, that the registry will accept
+    /// for publishing. If this isn't given, the limit is 2 MiB.
+    #[serde(default)]
+    pub max_package_size: Option<u64>,
 }
 
 pub struct PackageIndex {
@@ -104,9 +113,20 @@ impl PackageIndex {
         git_util::update_index(self.access_token.clone(), &repository)
             .with_context(|| format!("could not update package index"))?;
 
+        // Anything we've read before might have changed underneath us.
+        self.package_cache.lock().unwrap().clear();
+
         Ok(())
     }
 
+    /// The commit that the local copy of the index is currently at.
+    pub fn head_commit(&self) -> anyhow::Result<String> {
+        let repository = self.repository.lock().unwrap();
+        let commit = repository.head()?.peel_to_commit()?;
+
+        Ok(commit.id().to_string())
+    }
+
     pub fn config(&self) -> anyhow::Result<PackageIndexConfig> {
         let config_path = self.path.join("config.json");
         let contents = fs_err::read_to_string(config_path)?;
@@ -116,43 +136,135 @@ impl PackageIndex {
     /// Publish a package to the local copy of the index and attempt to push it
     /// to the remote index, allowing a certain number of retries.
     ///
+    /// If the push is rejected because somebody else pushed to the index first,
+    /// we reset to their changes and try again, unless they published the same
+    /// package version in the meantime.
+    ///
     /// Note that this method does not interact with any remote registry
     /// servers; it's intended for use with local registries or in the
     /// implementation of the registry server itself.
     pub fn publish(&self, manifest: &Manifest) -> anyhow::Result<()> {
-        let repo = self.repository.lock().unwrap();
         let package_path = self.package_path(&manifest.package.name);
+        let package_id = manifest.package_id();
 
-        // This package might not exist yet, so create its containing directory.
-        create_dir_all(package_path.parent().unwrap())?;
-
-        {
-            let mut file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&package_path)?;
+        self.push_change(
+            &format!("Publish {}", package_id),
+            &package_path,
+            &manifest.package.name,
+            || {
+                ensure!(
+                    !self.has_version(manifest)?,
+                    "package {} already exists in index",
+                    package_id
+                );
+
+                // This package might not exist yet, so create its containing
+                // directory. Resetting after a rejected push removes it again
+                // if this is the first package in its scope.
+                create_dir_all(package_path.parent().unwrap())?;
+
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&package_path)?;
+
+                // Package entries are newline-delimited JSON files. We assume
+                // here that the file is empty or already ends in a newline.
+                let mut entry = serde_json::to_string(&manifest)?;
+                entry.push('\n');
+                file.write_all(entry.as_bytes())?;
+
+                Ok(())
+            },
+        )
+    }
 
-            // Package entries are newline-delimited JSON files. We assume here
-            // that the file is empty or already ends in a newline.
-            let mut entry = serde_json::to_string(&manifest)?;
-            entry.push('\n');
-            file.write_all(entry.as_bytes())?;
+    /// Apply a change to a single file in the local copy of the index, commit
+    /// it and push it to the remote index, allowing a certain number of
+    /// retries. `apply` is run again on top of the remote's changes whenever
+    /// our push is rejected, so it should check that the change still makes
+    /// sense.
+    ///
+    /// If the change can't be applied or pushed, it's thrown away so that it
+    /// doesn't linger in the local copy of the index.
+    fn push_change<F>(
+        &self,
+        message: &str,
+        path: &Path,
+        name: &PackageName,
+        mut apply: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut() -> anyhow::Result<()>,
+    {
+        let repo = self.repository.lock().unwrap();
+        let mut attempt = 1;
+
+        loop {
+            let base = repo.head()?.peel_to_commit()?.id();
+
+            if let Err(err) = apply() {
+                git_util::reset_hard(&repo, base)?;
+                self.package_cache.lock().unwrap().remove(name);
+                return Err(err);
+            }
+
+            let result = git_util::commit_and_push(
+                &repo,
+                self.access_token.clone(),
+                message,
+                &self.path,
+                path,
+            );
+
+            // Blow away the cache for this package, since we've now modified
+            // the underlying file.
+            self.package_cache.lock().unwrap().remove(name);
+
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+
+            // Throw away our commit, whether we're about to replay it or give
+            // up on it.
+            git_util::reset_hard(&repo, base)?;
+
+            if attempt >= PUBLISH_ATTEMPTS {
+                return Err(err.context(format!(
+                    "could not push to package index after {} attempts",
+                    attempt
+                )));
+            }
+
+            log::warn!(
+                "Could not push '{}' to package index (attempt {}): {:?}",
+                message,
+                attempt,
+                err
+            );
+
+            // Replay our change on top of whatever was pushed before us.
+            git_util::update_index(self.access_token.clone(), &repo)
+                .context("could not update package index")?;
+            self.package_cache.lock().unwrap().clear();
+
+            attempt += 1;
         }
+    }
 
-        git_util::commit_and_push(
-            &repo,
-            self.access_token.clone(),
-            &format!("Publish {}", manifest.package_id()),
-            &self.path,
-            &package_path,
-        )?;
+    /// Whether the index already has an entry for the given package version.
+    fn has_version(&self, manifest: &Manifest) -> anyhow::Result<bool> {
+        if !self.package_path(&manifest.package.name).exists() {
+            return Ok(false);
+        }
 
-        // Blow away the cache for this package, since we've now modified the
-        // underlying file.
-        let mut package_cache = self.package_cache.lock().unwrap();
-        package_cache.remove(&manifest.package.name);
+        let metadata = self.get_package_metadata(&manifest.package.name)?;
 
-        Ok(())
+        Ok(metadata
+            .versions
+            .iter()
+            .any(|published| published.package.version == manifest.package.version))
     }
 
     /// Read the list of versions for a package from the index.
@@ -193,6 +305,43 @@ impl PackageIndex {
         }
     }
 
+    /// List every package that has an entry in the index.
+    pub fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        let mut names = Vec::new();
+
+        for scope_entry in fs_err::read_dir(&self.path)? {
+            let scope_entry = scope_entry?;
+            let scope = scope_entry.file_name();
+
+            // Skip the Git directory and anything else that isn't a scope.
+            match scope.to_str() {
+                Some(scope) if !scope.starts_with('.') && scope_entry.file_type()?.is_dir() => {}
+                _ => continue,
+            }
+
+            for package_entry in fs_err::read_dir(scope_entry.path())? {
+                let package_entry = package_entry?;
+
+                if !package_entry.file_type()?.is_file() {
+                    continue;
+                }
+
+                // Scopes also contain files like owners.json, which aren't
+                // valid package names and so are skipped here.
+                if let (Some(scope), Some(name)) =
+                    (scope.to_str(), package_entry.file_name().to_str())
+                {
+                    if let Ok(package_name) = PackageName::new(scope, name) {
+                        names.push(package_name);
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
     /// Read the list of owners for a scope from the index
     pub fn get_scope_owners(&self, scope: &str) -> anyhow::Result<Vec<u64>> {
         let mut path = self.path.clone();
@@ -248,6 +397,39 @@ impl PackageIndex {
         Ok(())
     }
 
+    /// Read the deprecations that a package's owners have marked on it.
+    pub fn get_deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        let mut deprecations = read_deprecations(&self.path.join(name.scope()))?;
+        Ok(deprecations.remove(name.name()).unwrap_or_default())
+    }
+
+    /// Mark some or all versions of a package as deprecated, replacing any
+    /// earlier deprecation of the same versions.
+    /// Similar to publish, this first applies the change to our local copy
+    /// and then attempts to push it to the remote index, retrying on top of
+    /// anybody else's changes.
+    pub fn deprecate(&self, name: &PackageName, deprecation: Deprecation) -> anyhow::Result<()> {
+        let scope_path = self.path.join(name.scope());
+        let path = scope_path.join(DEPRECATIONS_FILE_NAME);
+
+        self.push_change(&format!("Deprecate {}", name), &path, name, || {
+            let mut deprecations = read_deprecations(&scope_path)?;
+            let package_deprecations = deprecations.entry(name.name().to_owned()).or_default();
+
+            package_deprecations.retain(|existing| existing.versions != deprecation.versions);
+            package_deprecations.push(deprecation.clone());
+
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?;
+            file.write_all(serde_json::to_string(&deprecations)?.as_bytes())?;
+
+            Ok(())
+        })
+    }
+
     fn package_path(&self, name: &PackageName) -> PathBuf {
         // Each package has all of its versions stored in a folder based on its
         // scope and name.
@@ -263,6 +445,59 @@ pub struct PackageMetadata {
     pub versions: Vec<Manifest>,
 }
 
+/// The file in each scope of the index that holds deprecations for the
+/// scope's packages, keyed by package name.
+const DEPRECATIONS_FILE_NAME: &str = "deprecations.json";
+
+/// A note from a package's owners that some or all of its versions shouldn't
+/// be used anymore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deprecation {
+    /// The versions that are deprecated, or every version if this isn't given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versions: Option<VersionReq>,
+
+    /// Why the package is deprecated, shown to anyone who depends on it.
+    pub message: String,
+
+    /// A package to use instead, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<PackageName>,
+}
+
+impl Deprecation {
+    /// Whether this deprecation covers the given version. Prereleases are
+    /// covered along with the version that they lead up to.
+    pub fn applies_to(&self, version: &Version) -> bool {
+        let versions = match &self.versions {
+            Some(versions) => versions,
+            None => return true,
+        };
+
+        let mut release = version.clone();
+        release.pre.clear();
+
+        versions.matches(version) || versions.matches(&release)
+    }
+}
+
+/// Read the deprecations for every package in the scope at `scope_path`.
+pub(crate) fn read_deprecations(
+    scope_path: &Path,
+) -> anyhow::Result<BTreeMap<String, Vec<Deprecation>>> {
+    let path = scope_path.join(DEPRECATIONS_FILE_NAME);
+
+    match File::open(&path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("could not parse deprecations in {}", path.display())),
+
+        Err(error) => match error.kind() {
+            ErrorKind::NotFound => Ok(BTreeMap::new()),
+            _ => Err(error).context("failed to read deprecations"),
+        },
+    }
+}
+
 fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
     let registry_name = match (index_url.domain(), index_url.scheme()) {
         (Some(domain), _) => domain,
@@ -16,15 +16,31 @@ use crate::package_name::PackageName;
 /// * `roblox/roact@1.4.2`
 /// * `lpghatguy/asink@0.2.0-alpha.3`
 /// * `foo/bar@1`
+///
+/// Prereleases only match a requirement that names a prerelease, like
+/// `foo/bar@1.0.0-beta.1`, unless prereleases have been opted into with
+/// `with_prerelease`.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PackageReq {
     name: PackageName,
     version_req: VersionReq,
+    prerelease: bool,
 }
 
 impl PackageReq {
     pub fn new(name: PackageName, version_req: VersionReq) -> Self {
-        PackageReq { name, version_req }
+        PackageReq {
+            name,
+            version_req,
+            prerelease: false,
+        }
+    }
+
+    /// Also match prereleases of any version this requirement matches. For
+    /// example, `foo/bar@1.2.0` would then match `1.3.0-beta.1`.
+    pub fn with_prerelease(mut self) -> Self {
+        self.prerelease = true;
+        self
     }
 
     pub fn name(&self) -> &PackageName {
@@ -40,7 +56,54 @@ impl PackageReq {
     }
 
     pub fn matches(&self, name: &PackageName, version: &Version) -> bool {
-        self.name() == name && self.version_req.matches(version)
+        if self.name() != name {
+            return false;
+        }
+
+        if !version.is_prerelease() {
+            return self.version_req.matches(version);
+        }
+
+        if self.names_prerelease() && self.version_req.matches(version) {
+            return true;
+        }
+
+        if self.prerelease {
+            let mut release = version.clone();
+            release.pre.clear();
+
+            // Prereleases sort below their release, so a prerelease of the
+            // lowest version in the range is outside of it: `1.2.0-rc.1`
+            // doesn't satisfy `^1.2.0`. The range only reaches below the
+            // release if it also matches the release just before it.
+            return self.version_req.matches(&release)
+                && previous_release(&release)
+                    .map_or(false, |previous| self.version_req.matches(&previous));
+        }
+
+        false
+    }
+
+    /// Whether this requirement mentions a prerelease version. The semver
+    /// crate doesn't expose its comparators, but a `-` can only appear in the
+    /// displayed requirement as part of a prerelease tag.
+    fn names_prerelease(&self) -> bool {
+        self.version_req.to_string().contains('-')
+    }
+}
+
+/// The release that comes right before the given one, if there is one.
+fn previous_release(version: &Version) -> Option<Version> {
+    let (major, minor, patch) = (version.major, version.minor, version.patch);
+
+    if patch > 0 {
+        Some(Version::new(major, minor, patch - 1))
+    } else if minor > 0 {
+        Some(Version::new(major, minor - 1, u64::MAX))
+    } else if major > 0 {
+        Some(Version::new(major - 1, u64::MAX, u64::MAX))
+    } else {
+        None
     }
 }
 
@@ -178,6 +241,37 @@ mod test {
         no_version_at.unwrap_err();
     }
 
+    #[test]
+    fn prerelease() {
+        let name = PackageName::new("hello", "world").unwrap();
+        let beta: Version = "1.3.0-beta.1".parse().unwrap();
+
+        // Wide ranges never pick up prereleases by accident...
+        let wide: PackageReq = "hello/world@1.2.0".parse().unwrap();
+        assert!(!wide.matches(&name, &beta));
+        assert!(!PackageReq::new(name.clone(), VersionReq::any()).matches(&name, &beta));
+
+        // ...unless they're opted into...
+        assert!(wide.clone().with_prerelease().matches(&name, &beta));
+        assert!(!wide
+            .clone()
+            .with_prerelease()
+            .matches(&name, &"2.0.0-beta.1".parse().unwrap()));
+
+        // Prereleases of the lowest version in the range come before it.
+        assert!(!wide
+            .with_prerelease()
+            .matches(&name, &"1.2.0-rc.1".parse().unwrap()));
+        let at_least: PackageReq = "hello/world@>=1.2.0".parse().unwrap();
+        assert!(!at_least
+            .with_prerelease()
+            .matches(&name, &"1.2.0-rc.1".parse().unwrap()));
+
+        // ...or the requirement names a prerelease.
+        let named: PackageReq = "hello/world@1.3.0-alpha.2".parse().unwrap();
+        assert!(named.matches(&name, &beta));
+    }
+
     #[test]
     fn serialization() {
         let name = PackageName::new("lpghatguy", "asink").unwrap();
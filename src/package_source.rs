@@ -1,20 +1,25 @@
 mod in_memory;
+mod patch;
 mod registry;
 mod test_registry;
 
 pub use self::in_memory::InMemoryRegistry;
 use self::in_memory::InMemoryRegistrySource;
+pub use self::patch::PatchSource;
 pub use self::registry::Registry;
 pub use self::test_registry::TestRegistry;
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use anyhow::bail;
 use serde::Serialize;
 
-use crate::manifest::Manifest;
+use crate::manifest::{Manifest, Patch};
 use crate::package_contents::PackageContents;
 use crate::package_id::PackageId;
+use crate::package_index::Deprecation;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
@@ -22,12 +27,14 @@ pub enum PackageSourceId {
     DefaultRegistry,
     Git(String),
     Path(PathBuf),
+    Patch(PackageName),
 }
 
 #[derive(Clone)]
 pub struct PackageSourceMap {
     sources: HashMap<PackageSourceId, Box<PackageSource>>,
     source_order: Vec<PackageSourceId>,
+    registries: HashMap<String, PackageSourceId>,
 }
 
 impl PackageSourceMap {
@@ -38,6 +45,7 @@ impl PackageSourceMap {
         Self {
             sources,
             source_order: vec![PackageSourceId::DefaultRegistry],
+            registries: HashMap::new(),
         }
     }
 
@@ -68,8 +76,8 @@ impl PackageSourceMap {
                         PackageSourceId::Path(path) => {
                             Box::new(PackageSource::TestRegistry(TestRegistry::new(path.clone())))
                         }
-                        PackageSourceId::DefaultRegistry => {
-                            panic!("Default registry should never be added as a fallback source!")
+                        PackageSourceId::DefaultRegistry | PackageSourceId::Patch(_) => {
+                            panic!("{:?} should never be added as a fallback source!", fallback)
                         }
                     };
 
@@ -83,6 +91,91 @@ impl PackageSourceMap {
 
         Ok(())
     }
+
+    /// Loads every registry in the root manifest's `[registries]` section, so
+    /// that dependencies can ask for them by URL or by name.
+    ///
+    /// Like patches, these registries aren't part of the source order. They're
+    /// only searched for dependencies that ask for them.
+    pub fn add_registries(&mut self, manifest: &Manifest) -> anyhow::Result<()> {
+        let test_registry = matches!(
+            self.get(&PackageSourceId::DefaultRegistry),
+            Some(PackageSource::TestRegistry(_))
+        );
+
+        self.registries.insert(
+            manifest.package.registry.clone(),
+            PackageSourceId::DefaultRegistry,
+        );
+
+        for spec in manifest.registries.values() {
+            let source = if test_registry {
+                PackageSource::TestRegistry(TestRegistry::new(spec))
+            } else {
+                PackageSource::Registry(Registry::from_registry_spec(spec)?)
+            };
+
+            self.add_registry(spec, Box::new(source));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a source that dependencies can ask for with the given registry
+    /// URL, unless one was already added for it.
+    pub fn add_registry(&mut self, spec: &str, source: Box<PackageSource>) {
+        let id = PackageSourceId::Git(spec.to_owned());
+
+        self.sources.entry(id.clone()).or_insert(source);
+        self.registries.insert(spec.to_owned(), id);
+    }
+
+    /// Finds the source for a registry URL given by a dependency. Only the
+    /// default registry, its fallbacks and registries added with
+    /// `add_registries` can be used.
+    pub fn registry_id(&self, spec: &str) -> Option<PackageSourceId> {
+        if let Some(id) = self.registries.get(spec) {
+            return Some(id.clone());
+        }
+
+        let id = PackageSourceId::Git(spec.to_owned());
+        if self.sources.contains_key(&id) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// Loads the sources for every patch in the root manifest that points at a
+    /// directory or Git repository, relative to the project at `project_path`.
+    ///
+    /// Patch sources aren't part of the source order, they're only used by
+    /// resolution for the packages that they patch.
+    pub fn add_patches(&mut self, manifest: &Manifest, project_path: &Path) -> anyhow::Result<()> {
+        for (name, patch) in &manifest.patch {
+            let source = match patch {
+                Patch::Path { path } => PatchSource::from_path(&project_path.join(path))?,
+                Patch::Git { git, rev } => PatchSource::from_git(git, rev.as_deref())?,
+                Patch::Version { .. } => continue,
+            };
+
+            let patched_name = &source.manifest().package.name;
+            if patched_name != name {
+                bail!(
+                    "The patch for {} contains a different package, {}",
+                    name,
+                    patched_name
+                );
+            }
+
+            self.sources.insert(
+                PackageSourceId::Patch(name.clone()),
+                Box::new(PackageSource::Patch(source)),
+            );
+        }
+
+        Ok(())
+    }
 }
 
 pub trait PackageSourceProvider: Sync + Send + Clone {
@@ -99,6 +192,9 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
 
     /// Provide a list of fallback sources to search if this source can't provide a package
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>>;
+
+    /// Find the deprecations that a package's owners have marked on it.
+    fn deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>>;
 }
 
 #[derive(Clone)]
@@ -106,6 +202,7 @@ pub enum PackageSource {
     InMemory(InMemoryRegistrySource),
     Registry(Registry),
     TestRegistry(TestRegistry),
+    Patch(PatchSource),
 }
 
 impl PackageSourceProvider for PackageSource {
@@ -114,6 +211,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.update(),
             PackageSource::Registry(source) => source.update(),
             PackageSource::TestRegistry(source) => source.update(),
+            PackageSource::Patch(source) => source.update(),
         }
     }
 
@@ -122,6 +220,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.query(package_req),
             PackageSource::Registry(source) => source.query(package_req),
             PackageSource::TestRegistry(source) => source.query(package_req),
+            PackageSource::Patch(source) => source.query(package_req),
         }
     }
 
@@ -130,6 +229,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.download_package(package_id),
             PackageSource::Registry(source) => source.download_package(package_id),
             PackageSource::TestRegistry(source) => source.download_package(package_id),
+            PackageSource::Patch(source) => source.download_package(package_id),
         }
     }
 
@@ -138,6 +238,16 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.fallback_sources(),
             PackageSource::Registry(source) => source.fallback_sources(),
             PackageSource::TestRegistry(source) => source.fallback_sources(),
+            PackageSource::Patch(source) => source.fallback_sources(),
+        }
+    }
+
+    fn deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        match self {
+            PackageSource::InMemory(source) => source.deprecations(name),
+            PackageSource::Registry(source) => source.deprecations(name),
+            PackageSource::TestRegistry(source) => source.deprecations(name),
+            PackageSource::Patch(source) => source.deprecations(name),
         }
     }
 }
@@ -9,8 +9,9 @@ use std::sync::RwLock;
 use anyhow::format_err;
 
 use crate::{
-    manifest::Manifest, package_id::PackageId, package_req::PackageReq,
-    package_source::PackageSource, test_package::PackageBuilder,
+    manifest::Manifest, package_id::PackageId, package_index::Deprecation,
+    package_name::PackageName, package_req::PackageReq, package_source::PackageSource,
+    test_package::PackageBuilder,
 };
 
 use super::{PackageContents, PackageSourceId, PackageSourceProvider};
@@ -46,6 +47,17 @@ impl InMemoryRegistry {
         entries.push(PackageEntry { manifest, contents });
     }
 
+    /// Mark some or all versions of a package as deprecated.
+    pub fn deprecate<N>(&self, name: N, deprecation: Deprecation)
+    where
+        N: AsRef<str>,
+    {
+        let name = name.as_ref().parse().expect("invalid PackageName");
+        let mut deprecations = self.storage.deprecations.write().unwrap();
+
+        deprecations.entry(name).or_default().push(deprecation);
+    }
+
     /// Returns a handle to an object that can be used as a `PackageSource`.
     pub fn source(&self) -> PackageSource {
         PackageSource::InMemory(InMemoryRegistrySource {
@@ -81,9 +93,10 @@ impl PackageSourceProvider for InMemoryRegistrySource {
         let result = entries
             .iter()
             .filter(|entry| {
-                package_req
-                    .version_req()
-                    .matches(&entry.manifest.package.version)
+                package_req.matches(
+                    &entry.manifest.package.name,
+                    &entry.manifest.package.version,
+                )
             })
             .map(|entry| &entry.manifest)
             .cloned()
@@ -113,6 +126,11 @@ impl PackageSourceProvider for InMemoryRegistrySource {
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
         todo!("Implement in-memory fallback sources");
     }
+
+    fn deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        let deprecations = self.storage.deprecations.read().unwrap();
+        Ok(deprecations.get(name).cloned().unwrap_or_default())
+    }
 }
 
 struct PackageEntry {
@@ -123,4 +141,5 @@ struct PackageEntry {
 #[derive(Clone, Default)]
 struct Storage {
     contents: Arc<RwLock<HashMap<String, HashMap<String, Vec<PackageEntry>>>>>,
+    deprecations: Arc<RwLock<HashMap<PackageName, Vec<Deprecation>>>>,
 }
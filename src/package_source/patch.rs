@@ -0,0 +1,109 @@
+//! Defines a package source for a single package patched in from a directory
+//! on disk or a Git repository by the `[patch]` section of the root manifest.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::git_util;
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_index::Deprecation;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::PackageContents;
+
+use super::{PackageSourceId, PackageSourceProvider};
+
+#[derive(Clone)]
+pub struct PatchSource {
+    path: PathBuf,
+    manifest: Manifest,
+
+    /// Git patches are cloned into a temporary directory, which is held onto
+    /// here so that it lives as long as the source does.
+    #[allow(unused)]
+    temp_dir: Option<Arc<TempDir>>,
+}
+
+impl PatchSource {
+    /// Create a `PatchSource` from a directory containing a `wally.toml`.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let manifest = Manifest::load(path)
+            .with_context(|| format!("could not load patch from {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            manifest,
+            temp_dir: None,
+        })
+    }
+
+    /// Create a `PatchSource` by cloning a Git repository with a `wally.toml`
+    /// at its root.
+    pub fn from_git(url: &str, rev: Option<&str>) -> anyhow::Result<Self> {
+        let url = Url::parse(url)?;
+        let temp_dir = tempfile::tempdir()?;
+
+        let repository = git_util::clone(None, &url, temp_dir.path())
+            .with_context(|| format!("could not clone patch from {}", url))?;
+
+        if let Some(rev) = rev {
+            git_util::checkout(&repository, rev)?;
+        }
+
+        let manifest = Manifest::load(temp_dir.path())
+            .with_context(|| format!("could not load patch from {}", url))?;
+
+        Ok(Self {
+            path: temp_dir.path().to_owned(),
+            manifest,
+            temp_dir: Some(Arc::new(temp_dir)),
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+}
+
+impl PackageSourceProvider for PatchSource {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        let package = &self.manifest.package;
+
+        if package_req.matches(&package.name, &package.version) {
+            Ok(vec![self.manifest.clone()])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        if package_id != &self.manifest.package_id() {
+            bail!(
+                "Package {} does not exist, the patch contains {}",
+                package_id,
+                self.manifest.package_id()
+            );
+        }
+
+        PackageContents::pack_from_path(&self.path)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+
+    fn deprecations(&self, _name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        // Patches are chosen by the project itself, so there's nobody to
+        // deprecate them.
+        Ok(Vec::new())
+    }
+}
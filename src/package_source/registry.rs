@@ -9,7 +9,8 @@ use url::Url;
 use crate::auth::AuthStore;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
-use crate::package_index::PackageIndex;
+use crate::package_index::{Deprecation, PackageIndex};
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
@@ -121,4 +122,8 @@ impl PackageSourceProvider for Registry {
 
         Ok(sources)
     }
+
+    fn deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        self.index()?.get_deprecations(name)
+    }
 }
@@ -6,7 +6,8 @@ use fs_err::File;
 
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
-use crate::package_index::PackageIndexConfig;
+use crate::package_index::{read_deprecations, Deprecation, PackageIndexConfig};
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
@@ -95,4 +96,11 @@ impl PackageSourceProvider for TestRegistry {
 
         Ok(sources)
     }
+
+    fn deprecations(&self, name: &PackageName) -> anyhow::Result<Vec<Deprecation>> {
+        let scope_path = self.path.join("index").join(name.scope());
+        let mut deprecations = read_deprecations(&scope_path)?;
+
+        Ok(deprecations.remove(name.name()).unwrap_or_default())
+    }
 }
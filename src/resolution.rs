@@ -3,11 +3,14 @@ use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use anyhow::bail;
 use anyhow::format_err;
-use semver::Version;
+use anyhow::Context;
+use semver::{Version, VersionReq};
 use serde::Serialize;
 
-use crate::manifest::{Manifest, Realm};
+use crate::manifest::{Dependency, Manifest, Patch, Realm};
 use crate::package_id::PackageId;
+use crate::package_index::Deprecation;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
 
@@ -33,6 +36,16 @@ pub struct Resolve {
 
     /// Graph of all dependencies originating from the "dev" dependency realm.
     pub dev_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageId>>,
+
+    /// Features turned on for each package, combined from every request for
+    /// that package. Packages without any features turned on are left out.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<PackageId, BTreeSet<String>>,
+
+    /// Packages in the graph that their owners have deprecated, along with
+    /// the deprecation that covers the activated version.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub deprecated: BTreeMap<PackageId, Deprecation>,
 }
 
 impl Resolve {
@@ -46,6 +59,22 @@ impl Resolve {
         };
         dependencies.insert(dep_name, dep);
     }
+
+    /// Packages that had more than one version activated, which happens when
+    /// requirements ask for SemVer incompatible versions of the same package.
+    pub fn duplicates(&self) -> BTreeMap<PackageName, Vec<Version>> {
+        let mut versions: BTreeMap<PackageName, Vec<Version>> = BTreeMap::new();
+
+        for package_id in &self.activated {
+            versions
+                .entry(package_id.name().clone())
+                .or_default()
+                .push(package_id.version().clone());
+        }
+
+        versions.retain(|_, versions| versions.len() > 1);
+        versions
+    }
 }
 
 /// A single node in the package resolution graph.
@@ -57,12 +86,41 @@ pub struct ResolvePackageMetadata {
     pub realm: Realm,
     pub origin_realm: Realm,
     pub source_registry: PackageSourceId,
+
+    /// The package's license, used to check it against the root manifest's
+    /// `[licenses]` policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+/// Options that change which versions `resolve` picks.
+#[derive(Debug, Default, Clone)]
+pub struct ResolveOptions {
+    /// Allow prereleases of versions that requirements match, instead of only
+    /// picking prereleases that requirements name explicitly.
+    pub prerelease: bool,
+
+    /// Pick the lowest version that satisfies each requirement instead of the
+    /// highest, so that the lower bounds of requirements can be tested.
+    pub minimal_versions: bool,
+}
+
+impl ResolveOptions {
+    /// The order to try versions of a package in, most preferred first.
+    fn version_order(&self, a: &Version, b: &Version) -> Ordering {
+        if self.minimal_versions {
+            a.cmp(b)
+        } else {
+            b.cmp(a)
+        }
+    }
 }
 
 pub fn resolve(
     root_manifest: &Manifest,
     try_to_use: &BTreeSet<PackageId>,
     package_sources: &PackageSourceMap,
+    options: &ResolveOptions,
 ) -> anyhow::Result<Resolve> {
     let mut resolve = Resolve::default();
 
@@ -75,44 +133,79 @@ pub fn resolve(
             realm: root_manifest.package.realm,
             origin_realm: root_manifest.package.realm,
             source_registry: PackageSourceId::DefaultRegistry,
+            license: root_manifest.package.license.clone(),
         },
     );
 
+    // Nothing turns on the root package's features, but we still check them
+    // so that mistakes show up before the package is published.
+    root_manifest.expand_features(root_manifest.features.keys())?;
+
+    // Manifests of every activated package, used to turn on more of their
+    // features if a later request asks for them.
+    let mut manifests = BTreeMap::new();
+    manifests.insert(root_manifest.package_id(), root_manifest.clone());
+
     // Queue of all dependency requests that need to be resolved.
     let mut packages_to_visit = VecDeque::new();
 
-    for (alias, req) in &root_manifest.dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Shared,
-            origin_realm: Realm::Shared,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
-    }
+    let root_realms = [
+        (Realm::Shared, &root_manifest.dependencies),
+        (Realm::Server, &root_manifest.server_dependencies),
+        (Realm::Dev, &root_manifest.dev_dependencies),
+    ];
 
-    for (alias, req) in &root_manifest.server_dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Server,
-            origin_realm: Realm::Server,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
-    }
+    for &(realm, dependencies) in &root_realms {
+        for (alias, dependency) in dependencies {
+            if dependency.is_optional() {
+                continue;
+            }
 
-    for (alias, req) in &root_manifest.dev_dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Dev,
-            origin_realm: Realm::Dev,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
+            packages_to_visit.push_back(DependencyRequest {
+                request_source: root_manifest.package_id(),
+                request_realm: realm,
+                origin_realm: realm,
+                package_alias: alias.clone(),
+                package_req: dependency.req().clone(),
+                features: dependency.features().to_vec(),
+                registry: dependency
+                    .registry()
+                    .map(|registry| root_manifest.registry_spec(registry).to_owned()),
+            });
+        }
     }
 
     // Workhorse loop: resolve all dependencies, depth-first.
-    'outer: while let Some(dependency_request) = packages_to_visit.pop_front() {
+    'outer: while let Some(mut dependency_request) = packages_to_visit.pop_front() {
+        // Patches replace whatever was asked for, so they're applied before we
+        // go looking for candidates.
+        let patch_source = apply_patch(root_manifest, package_sources, &mut dependency_request)?;
+
+        // Prereleases are only picked when they're asked for: by naming one in
+        // the requirement, by opting in, or by already being in the lockfile.
+        let prerelease_req = dependency_request.package_req.clone().with_prerelease();
+        let allowed = |package_id: &PackageId| {
+            dependency_request.package_req.matches_id(package_id)
+                || ((options.prerelease || try_to_use.contains(package_id))
+                    && prerelease_req.matches_id(package_id))
+        };
+
+        // Patched packages and dependencies that ask for a specific registry
+        // skip the usual search through our sources.
+        let pinned_source = match (patch_source, &dependency_request.registry) {
+            (Some(source), _) => Some(source),
+            (None, Some(spec)) => Some(package_sources.registry_id(spec).ok_or_else(|| {
+                format_err!(
+                    "{} asks for {} from the registry {}, which is not the project's \
+                     registry, one of its fallbacks or in the project's [registries]",
+                    dependency_request.request_source,
+                    dependency_request.package_req,
+                    spec
+                )
+            })?),
+            (None, None) => None,
+        };
+
         // Locate all already-activated packages that might match this
         // dependency request.
         let mut matching_activated: Vec<_> = resolve
@@ -123,18 +216,28 @@ pub fn resolve(
             .collect();
 
         // Sort our list of candidates by descending version so that we can pick
-        // newest candidates first.
-        matching_activated.sort_by(|a, b| b.version().cmp(a.version()));
+        // newest candidates first, or oldest first when asked for minimal
+        // versions.
+        matching_activated.sort_by(|a, b| options.version_order(a.version(), b.version()));
 
-        // Check for the highest version already-activated package that matches
-        // our constraints.
+        // Check for the preferred already-activated package that matches our
+        // constraints.
         for package_id in &matching_activated {
-            if dependency_request.package_req.matches_id(package_id) {
+            if allowed(package_id) {
                 let metadata = resolve
                     .metadata
                     .get_mut(package_id)
                     .expect("activated package was missing metadata");
 
+                // A package from another registry may have the same name and
+                // version, but it isn't the package that was asked for. Looking
+                // in the pinned source instead reports the conflict.
+                if let Some(source) = &pinned_source {
+                    if metadata.source_registry != *source {
+                        continue;
+                    }
+                }
+
                 // [ origin_realm clarification ]
                 // We want to set the origin to the most restrictive origin possible.
                 // For example we want to keep packages in the dev realm unless a dependency
@@ -160,33 +263,70 @@ pub fn resolve(
                     package_id.clone(),
                 );
 
+                // Features are unified across the graph, so this request may
+                // turn on optional dependencies that earlier ones didn't.
+                if !dependency_request.features.is_empty() {
+                    let manifest = &manifests[package_id];
+                    let already_enabled = resolve
+                        .features
+                        .get(package_id)
+                        .cloned()
+                        .unwrap_or_default();
+                    let enabled = manifest.expand_features(
+                        already_enabled.iter().chain(&dependency_request.features),
+                    )?;
+
+                    if enabled.len() > already_enabled.len() {
+                        queue_dependencies(
+                            &mut packages_to_visit,
+                            package_id,
+                            manifest,
+                            realm_match,
+                            |alias, dependency| {
+                                dependency.is_optional()
+                                    && enabled.contains(alias)
+                                    && !already_enabled.contains(alias)
+                            },
+                        );
+
+                        resolve.features.insert(package_id.clone(), enabled);
+                    }
+                }
+
                 continue 'outer;
             }
         }
 
-        // Look through all our packages sources in order of priority
-        let (source_registry, mut candidates) = package_sources
-            .source_order()
-            .iter()
-            .find_map(|source| {
+        let (source_registry, mut candidates) = match &pinned_source {
+            Some(source) => {
                 let registry = package_sources.get(source).unwrap();
+                (source, registry.query(&prerelease_req)?)
+            }
 
-                // Pull all of the possible candidate versions of the package we're
-                // looking for from the highest priority source which has them.
-                match registry.query(&dependency_request.package_req) {
-                    Ok(manifests) => Some((source, manifests)),
-                    Err(_) => None,
-                }
-            })
-            .ok_or_else(|| {
-                format_err!(
-                    "Failed to find a source for {}",
-                    dependency_request.package_req
-                )
-            })?;
+            // Look through all our packages sources in order of priority
+            None => package_sources
+                .source_order()
+                .iter()
+                .find_map(|source| {
+                    let registry = package_sources.get(source).unwrap();
+
+                    // Pull all of the possible candidate versions of the package we're
+                    // looking for from the highest priority source which has them.
+                    match registry.query(&prerelease_req) {
+                        Ok(manifests) => Some((source, manifests)),
+                        Err(_) => None,
+                    }
+                })
+                .ok_or_else(|| {
+                    format_err!(
+                        "Failed to find a source for {}",
+                        dependency_request.package_req
+                    )
+                })?,
+        };
 
         // Sort our candidate packages by descending version, so that we try the
-        // highest versions first.
+        // highest versions first. With minimal versions, the lowest go first.
         //
         // Additionally, if there were any packages that were previously used by
         // our lockfile (in `try_to_use`), prioritize those first. This
@@ -198,12 +338,16 @@ pub fn resolve(
             match (contains_a, contains_b) {
                 (true, false) => Ordering::Less,
                 (false, true) => Ordering::Greater,
-                _ => b.package.version.cmp(&a.package.version),
+                _ => options.version_order(&a.package.version, &b.package.version),
             }
         });
 
         let filtered_candidates = candidates.iter().filter(|candidate| {
-            Realm::is_dependency_valid(dependency_request.request_realm, candidate.package.realm)
+            allowed(&candidate.package_id())
+                && Realm::is_dependency_valid(
+                    dependency_request.request_realm,
+                    candidate.package.realm,
+                )
         });
 
         let mut conflicting = Vec::new();
@@ -211,7 +355,8 @@ pub fn resolve(
         for candidate in filtered_candidates {
             // Conflicts occur if two packages are SemVer compatible. We choose
             // to only allow one compatible copy of a given package to prevent
-            // common user errors.
+            // common user errors. Incompatible copies, like 1.x and 2.x, are
+            // activated side by side and installed into their own folders.
 
             let has_conflicting = matching_activated
                 .iter()
@@ -232,6 +377,13 @@ pub fn resolve(
                 candidate.package.version.clone(),
             );
 
+            // Packages from registries are read leniently, so packages that
+            // need a newer Wally are only caught once they're picked.
+            candidate
+                .package
+                .check_compatible()
+                .with_context(|| format!("Cannot use {}", candidate_id))?;
+
             resolve.activate(
                 dependency_request.request_source.clone(),
                 dependency_request.package_alias.to_owned(),
@@ -245,29 +397,39 @@ pub fn resolve(
                     realm: candidate.package.realm,
                     origin_realm: dependency_request.origin_realm,
                     source_registry: source_registry.clone(),
+                    license: candidate.package.license.clone(),
                 },
             );
 
-            for (alias, req) in &candidate.dependencies {
-                packages_to_visit.push_back(DependencyRequest {
-                    request_source: candidate_id.clone(),
-                    request_realm: Realm::Shared,
-                    origin_realm: dependency_request.origin_realm,
-                    package_alias: alias.clone(),
-                    package_req: req.clone(),
-                })
+            // Owners can deprecate versions long after they were published,
+            // so this is checked whenever a package enters the graph.
+            let deprecation = package_sources
+                .get(source_registry)
+                .unwrap()
+                .deprecations(&candidate.package.name)?
+                .into_iter()
+                .find(|deprecation| deprecation.applies_to(&candidate.package.version));
+
+            if let Some(deprecation) = deprecation {
+                resolve.deprecated.insert(candidate_id.clone(), deprecation);
             }
 
-            for (alias, req) in &candidate.server_dependencies {
-                packages_to_visit.push_back(DependencyRequest {
-                    request_source: candidate_id.clone(),
-                    request_realm: Realm::Server,
-                    origin_realm: dependency_request.origin_realm,
-                    package_alias: alias.clone(),
-                    package_req: req.clone(),
-                })
+            let enabled = candidate.expand_features(&dependency_request.features)?;
+
+            queue_dependencies(
+                &mut packages_to_visit,
+                &candidate_id,
+                candidate,
+                dependency_request.origin_realm,
+                |alias, dependency| !dependency.is_optional() || enabled.contains(alias),
+            );
+
+            if !enabled.is_empty() {
+                resolve.features.insert(candidate_id.clone(), enabled);
             }
 
+            manifests.insert(candidate_id, candidate.clone());
+
             continue 'outer;
         }
 
@@ -298,6 +460,78 @@ pub fn resolve(
     Ok(resolve)
 }
 
+/// Queue requests for the dependencies of an activated package that `wanted`
+/// picks out by alias.
+fn queue_dependencies(
+    packages_to_visit: &mut VecDeque<DependencyRequest>,
+    package_id: &PackageId,
+    manifest: &Manifest,
+    origin_realm: Realm,
+    wanted: impl Fn(&str, &Dependency) -> bool,
+) {
+    let realms = [
+        (Realm::Shared, &manifest.dependencies),
+        (Realm::Server, &manifest.server_dependencies),
+    ];
+
+    for &(realm, dependencies) in &realms {
+        for (alias, dependency) in dependencies {
+            if !wanted(alias, dependency) {
+                continue;
+            }
+
+            packages_to_visit.push_back(DependencyRequest {
+                request_source: package_id.clone(),
+                request_realm: realm,
+                origin_realm,
+                package_alias: alias.clone(),
+                package_req: dependency.req().clone(),
+                features: dependency.features().to_vec(),
+                registry: dependency
+                    .registry()
+                    .map(|registry| manifest.registry_spec(registry).to_owned()),
+            });
+        }
+    }
+}
+
+/// Rewrite a dependency request according to the root manifest's patch for the
+/// requested package, if there is one. Patches that point at a directory or a
+/// Git repository pin the request to the version found there, and return the
+/// source that it has to come from.
+fn apply_patch(
+    root_manifest: &Manifest,
+    package_sources: &PackageSourceMap,
+    dependency_request: &mut DependencyRequest,
+) -> anyhow::Result<Option<PackageSourceId>> {
+    let name = dependency_request.package_req.name().clone();
+
+    let patch = match root_manifest.patch.get(&name) {
+        Some(patch) => patch,
+        None => return Ok(None),
+    };
+
+    if let Patch::Version { version } = patch {
+        dependency_request.package_req = PackageReq::new(name, version.clone());
+        return Ok(None);
+    }
+
+    let source_id = PackageSourceId::Patch(name.clone());
+    let source = package_sources
+        .get(&source_id)
+        .ok_or_else(|| format_err!("The patch for {} was not loaded", name))?;
+
+    let patched =
+        source.query(&PackageReq::new(name.clone(), VersionReq::any()).with_prerelease())?;
+    let version = match patched.first() {
+        Some(manifest) => &manifest.package.version,
+        None => bail!("The patch for {} does not contain the package", name),
+    };
+
+    dependency_request.package_req = PackageReq::new(name, VersionReq::exact(version));
+    Ok(Some(source_id))
+}
+
 fn compatible(a: &Version, b: &Version) -> bool {
     if a == b {
         return true;
@@ -316,20 +550,30 @@ pub struct DependencyRequest {
     origin_realm: Realm,
     package_alias: String,
     package_req: PackageReq,
+    features: Vec<String>,
+    registry: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::path::Path;
+
     use crate::{
-        package_name::PackageName, package_source::InMemoryRegistry, test_package::PackageBuilder,
+        manifest::MANIFEST_FILE_NAME, package_source::InMemoryRegistry,
+        test_package::PackageBuilder,
     };
 
     fn test_project(registry: InMemoryRegistry, package: PackageBuilder) -> anyhow::Result<()> {
         let package_sources = PackageSourceMap::new(Box::new(registry.source()));
         let manifest = package.into_manifest();
-        let resolve = resolve(&manifest, &Default::default(), &package_sources)?;
+        let resolve = resolve(
+            &manifest,
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
         insta::assert_yaml_snapshot!(resolve);
         Ok(())
     }
@@ -442,7 +686,13 @@ mod tests {
         let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Server", "biff/server@1.0.0");
 
         let package_sources = PackageSourceMap::new(Box::new(registry.source()));
-        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        let err = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )
+        .unwrap_err();
         insta::assert_display_snapshot!(err);
     }
 
@@ -460,11 +710,21 @@ mod tests {
 
         let package_sources = PackageSourceMap::new(Box::new(registry.source()));
 
-        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
         insta::assert_yaml_snapshot!("one_dependency_no_upgrade", resolved);
 
         registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
-        let new_resolved = resolve(root.manifest(), &resolved.activated, &package_sources)?;
+        let new_resolved = resolve(
+            root.manifest(),
+            &resolved.activated,
+            &package_sources,
+            &Default::default(),
+        )?;
         insta::assert_yaml_snapshot!("one_dependency_no_upgrade", new_resolved);
 
         Ok(())
@@ -480,7 +740,12 @@ mod tests {
 
         let package_sources = PackageSourceMap::new(Box::new(registry.source()));
 
-        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
         insta::assert_yaml_snapshot!(resolved);
 
         // We can indicate that we'd like to upgrade a package by just removing
@@ -493,9 +758,452 @@ mod tests {
             .collect();
 
         registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
-        let new_resolved = resolve(root.manifest(), &try_to_use, &package_sources)?;
+        let new_resolved = resolve(
+            root.manifest(),
+            &try_to_use,
+            &package_sources,
+            &Default::default(),
+        )?;
         insta::assert_yaml_snapshot!(new_resolved);
 
         Ok(())
     }
+
+    /// Patches replace a package everywhere in the graph, even where it's only
+    /// depended on by other packages.
+    #[test]
+    fn patch_version() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
+        registry.publish(
+            PackageBuilder::new("biff/one-dependency@1.0.0")
+                .with_dep("Minimal", "biff/minimal@1.1.0"),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("OneDependency", "biff/one-dependency@1.0.0")
+            .with_patch(
+                "biff/minimal",
+                Patch::Version {
+                    version: "=1.0.0".parse().unwrap(),
+                },
+            );
+
+        test_project(registry, root)
+    }
+
+    #[test]
+    fn patch_path() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/one-dependency@1.0.0")
+                .with_dep("Minimal", "biff/minimal@1.0.0"),
+        );
+
+        let patch_dir = tempfile::tempdir()?;
+        let patched = PackageBuilder::new("biff/minimal@1.0.1");
+        fs_err::write(
+            patch_dir.path().join(MANIFEST_FILE_NAME),
+            toml::to_string_pretty(patched.manifest())?,
+        )?;
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Minimal", "biff/minimal@1.0.0")
+            .with_dep("OneDependency", "biff/one-dependency@1.0.0")
+            .with_patch(
+                "biff/minimal",
+                Patch::Path {
+                    path: patch_dir.path().to_owned(),
+                },
+            );
+
+        let mut package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        package_sources.add_patches(root.manifest(), Path::new("."))?;
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+
+        let patched_id = patched.manifest().package_id();
+        let one_dependency_id: PackageId = "biff/one-dependency@1.0.0".parse().unwrap();
+
+        assert_eq!(resolved.activated.len(), 3);
+        assert!(resolved.activated.contains(&patched_id));
+        assert_eq!(
+            resolved.metadata[&patched_id].source_registry,
+            PackageSourceId::Patch(patched_id.name().clone())
+        );
+        assert_eq!(
+            resolved.shared_dependencies[&one_dependency_id]["Minimal"],
+            patched_id
+        );
+
+        Ok(())
+    }
+
+    /// When requirements ask for SemVer incompatible versions of a package,
+    /// both versions should be activated. Here, A depends on B and C, which
+    /// depend on different major versions of D.
+    #[test]
+    fn multiple_major_versions() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@2.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@2.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+        insta::assert_yaml_snapshot!(resolved);
+
+        let duplicates = resolved.duplicates();
+        let d: PackageName = "biff/d".parse().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates[&d],
+            vec![Version::new(1, 0, 0), Version::new(2, 0, 0)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prerelease_only_when_asked() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.1.0-beta.1"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let minimal = |resolved: &Resolve| -> Version {
+            resolved
+                .activated
+                .iter()
+                .find(|package_id| package_id.name().name() == "minimal")
+                .map(|package_id| package_id.version().clone())
+                .unwrap()
+        };
+
+        let stable = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+        assert_eq!(minimal(&stable), Version::new(1, 0, 0));
+
+        let prerelease = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &ResolveOptions {
+                prerelease: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(minimal(&prerelease), "1.1.0-beta.1".parse().unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_versions_skip_prerelease_of_lower_bound() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.2.0-rc.1"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.2.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.2.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &ResolveOptions {
+                minimal_versions: true,
+                prerelease: true,
+            },
+        )?;
+
+        let minimal: PackageId = "biff/minimal@1.2.0".parse().unwrap();
+        assert!(resolved.activated.contains(&minimal));
+        assert_eq!(resolved.activated.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_versions() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("C", "biff/c@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.2.0").with_dep("C", "biff/c@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.3.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0").with_dep("B", "biff/b@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &ResolveOptions {
+                minimal_versions: true,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_yaml_snapshot!(resolved);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unified_features() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/ui@1.0.0")
+                .with_detailed_dep("Roact", "biff/roact@1.0.0", true, Vec::<String>::new())
+                .with_detailed_dep("Fusion", "biff/fusion@1.0.0", true, Vec::<String>::new())
+                .with_feature("roact", vec!["Roact"])
+                .with_feature("all", vec!["roact", "Fusion"]),
+        );
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_detailed_dep(
+            "UI",
+            "biff/ui@1.0.0",
+            false,
+            vec!["roact"],
+        ));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_detailed_dep(
+            "UI",
+            "biff/ui@1.0.0",
+            false,
+            vec!["Fusion"],
+        ));
+        registry.publish(PackageBuilder::new("biff/roact@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/fusion@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        test_project(registry, root)
+    }
+
+    #[test]
+    fn optional_dependency_off_by_default() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/ui@1.0.0").with_detailed_dep(
+            "Roact",
+            "biff/roact@1.0.0",
+            true,
+            Vec::<String>::new(),
+        ));
+        registry.publish(PackageBuilder::new("biff/roact@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0").with_dep("UI", "biff/ui@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+
+        let roact: PackageId = "biff/roact@1.0.0".parse().unwrap();
+        assert!(!resolved.activated.contains(&roact));
+        assert!(resolved.features.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_unknown_feature() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/ui@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0").with_detailed_dep(
+            "UI",
+            "biff/ui@1.0.0",
+            false,
+            vec!["missing"],
+        );
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )
+        .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("no feature or optional dependency"));
+    }
+
+    #[test]
+    fn dependency_registry() -> anyhow::Result<()> {
+        let public = InMemoryRegistry::new();
+        public.publish(PackageBuilder::new("biff/util@1.0.0"));
+        public.publish(PackageBuilder::new("biff/b@1.0.0"));
+
+        let internal = InMemoryRegistry::new();
+        internal.publish(PackageBuilder::new("biff/util@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_registry("internal", "https://internal.example.com/index")
+            .with_registry_dep("Util", "biff/util@1.0.0", "internal")
+            .with_dep("B", "biff/b@1.0.0");
+
+        let mut package_sources = PackageSourceMap::new(Box::new(public.source()));
+        package_sources.add_registry(
+            "https://internal.example.com/index",
+            Box::new(internal.source()),
+        );
+
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+
+        let util: PackageId = "biff/util@1.0.0".parse().unwrap();
+        let b: PackageId = "biff/b@1.0.0".parse().unwrap();
+        assert_eq!(
+            resolved.metadata[&util].source_registry,
+            PackageSourceId::Git("https://internal.example.com/index".to_owned())
+        );
+        assert_eq!(
+            resolved.metadata[&b].source_registry,
+            PackageSourceId::DefaultRegistry
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_registry_conflicts_with_activated() {
+        let public = InMemoryRegistry::new();
+        public.publish(PackageBuilder::new("biff/util@1.0.0"));
+        public.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("Util", "biff/util@1.0.0"));
+        public.publish(PackageBuilder::new("biff/c@1.0.0").with_registry_dep(
+            "Util",
+            "biff/util@1.0.0",
+            "https://internal.example.com/index",
+        ));
+
+        let internal = InMemoryRegistry::new();
+        internal.publish(PackageBuilder::new("biff/util@1.0.0"));
+
+        // The public copy of biff/util is activated through biff/b before
+        // biff/c asks for the internal one, which has the same version.
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let mut package_sources = PackageSourceMap::new(Box::new(public.source()));
+        package_sources.add_registry(
+            "https://internal.example.com/index",
+            Box::new(internal.source()),
+        );
+
+        let err = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("conflicted"));
+    }
+
+    #[test]
+    fn fail_newer_edition() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/util@1.0.0").with_edition("2099"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0").with_dep("Util", "biff/util@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )
+        .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("Upgrade Wally to work on this package"));
+    }
+
+    #[test]
+    fn fail_unknown_registry() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/util@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0").with_registry_dep(
+            "Util",
+            "biff/util@1.0.0",
+            "https://unknown.example.com/index",
+        );
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )
+        .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("https://unknown.example.com/index"));
+    }
+
+    #[test]
+    fn deprecated_dependency() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/old-util@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/util@2.0.0"));
+        registry.publish(PackageBuilder::new("biff/new-util@1.0.0"));
+
+        let deprecation = Deprecation {
+            versions: Some(VersionReq::parse("<2.0.0").unwrap()),
+            message: String::from("Merged into biff/new-util"),
+            replacement: Some("biff/new-util".parse().unwrap()),
+        };
+        registry.deprecate("biff/old-util", deprecation.clone());
+        registry.deprecate("biff/util", deprecation.clone());
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("OldUtil", "biff/old-util@1.0.0")
+            .with_dep("Util", "biff/util@2.0.0")
+            .with_dep("NewUtil", "biff/new-util@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &Default::default(),
+        )?;
+
+        let old_util: PackageId = "biff/old-util@1.0.0".parse().unwrap();
+        assert_eq!(resolved.deprecated.len(), 1);
+        assert_eq!(resolved.deprecated.get(&old_util), Some(&deprecation));
+
+        Ok(())
+    }
 }
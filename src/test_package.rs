@@ -8,7 +8,7 @@ use std::{
 use zip::write::{FileOptions, ZipWriter};
 
 use crate::{
-    manifest::{Manifest, Package, Realm},
+    manifest::{Dependency, DetailedDependency, Edition, Manifest, Package, Patch, Realm},
     package_contents::PackageContents,
     package_id::PackageId,
     package_req::PackageReq,
@@ -36,16 +36,26 @@ impl PackageBuilder {
                 description: None,
                 license: None,
                 authors: Vec::new(),
+                keywords: Vec::new(),
+                categories: Vec::new(),
+                readme: None,
                 include: Vec::new(),
                 exclude: Vec::new(),
                 private: false,
                 homepage: None,
                 repository: None,
+                documentation: None,
+                wally_version: None,
+                edition: Default::default(),
             },
             place: Default::default(),
             dependencies: Default::default(),
             server_dependencies: Default::default(),
             dev_dependencies: Default::default(),
+            features: Default::default(),
+            registries: Default::default(),
+            patch: Default::default(),
+            licenses: Default::default(),
         };
 
         Self {
@@ -66,7 +76,67 @@ impl PackageBuilder {
     {
         let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
 
-        self.manifest.dependencies.insert(alias.into(), req);
+        self.manifest.dependencies.insert(alias.into(), req.into());
+        self
+    }
+
+    /// Add a shared dependency given as a table, for dependencies that are
+    /// optional or turn on features.
+    pub fn with_detailed_dep<A, R, F>(
+        mut self,
+        alias: A,
+        package_req: R,
+        optional: bool,
+        features: F,
+    ) -> Self
+    where
+        A: Into<String>,
+        R: AsRef<str>,
+        F: IntoIterator,
+        F::Item: Into<String>,
+    {
+        let version: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.dependencies.insert(
+            alias.into(),
+            Dependency::Detailed(DetailedDependency {
+                version,
+                optional,
+                features: features.into_iter().map(Into::into).collect(),
+                registry: None,
+            }),
+        );
+        self
+    }
+
+    /// Add a shared dependency that has to come from the given registry, which
+    /// is either a URL or a name from `[registries]`.
+    pub fn with_registry_dep<A, R, G>(mut self, alias: A, package_req: R, registry: G) -> Self
+    where
+        A: Into<String>,
+        R: AsRef<str>,
+        G: Into<String>,
+    {
+        let version: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.dependencies.insert(
+            alias.into(),
+            Dependency::Detailed(DetailedDependency {
+                version,
+                optional: false,
+                features: Vec::new(),
+                registry: Some(registry.into()),
+            }),
+        );
+        self
+    }
+
+    pub fn with_registry<N, U>(mut self, name: N, url: U) -> Self
+    where
+        N: Into<String>,
+        U: Into<String>,
+    {
+        self.manifest.registries.insert(name.into(), url.into());
         self
     }
 
@@ -77,10 +147,71 @@ impl PackageBuilder {
     {
         let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
 
-        self.manifest.server_dependencies.insert(alias.into(), req);
+        self.manifest
+            .server_dependencies
+            .insert(alias.into(), req.into());
         self
     }
 
+    pub fn with_feature<N, E>(mut self, name: N, enables: E) -> Self
+    where
+        N: Into<String>,
+        E: IntoIterator,
+        E::Item: Into<String>,
+    {
+        self.manifest
+            .features
+            .insert(name.into(), enables.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn with_patch<N>(mut self, name: N, patch: Patch) -> Self
+    where
+        N: AsRef<str>,
+    {
+        let name = name.as_ref().parse().expect("invalid PackageName");
+
+        self.manifest.patch.insert(name, patch);
+        self
+    }
+
+    pub fn with_keywords<K>(mut self, keywords: K) -> Self
+    where
+        K: IntoIterator,
+        K::Item: Into<String>,
+    {
+        self.manifest.package.keywords = keywords.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_categories<C>(mut self, categories: C) -> Self
+    where
+        C: IntoIterator,
+        C::Item: Into<String>,
+    {
+        self.manifest.package.categories = categories.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_edition<E>(mut self, edition: E) -> Self
+    where
+        E: Into<String>,
+    {
+        self.manifest.package.edition = Edition::new(edition);
+        self
+    }
+
+    /// Set the package's readme and add it to the package's files.
+    pub fn with_readme<P, C>(mut self, path: P, contents: C) -> Self
+    where
+        P: Into<String>,
+        C: Into<String>,
+    {
+        let path = path.into();
+        self.manifest.package.readme = Some(path.clone().into());
+        self.with_file(path, contents)
+    }
+
     pub fn with_file<P, C>(mut self, path: P, contents: C) -> Self
     where
         P: Into<String>,
@@ -60,6 +60,31 @@ fn locked_catches_dated_packages() {
     assert!(result.is_err(), "Should fail!");
 }
 
+#[test]
+fn disallowed_license() {
+    let source_project = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",))
+        .join("disallowed-license");
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    let result = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            minimal_versions: false,
+        }),
+    }
+    .run();
+
+    let message = format!("{:#}", result.unwrap_err());
+    assert!(message.contains("[licenses]"), "{}", message);
+    assert!(message.contains("biff/minimal@0.1.0"), "{}", message);
+}
+
 fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
     let source_project =
         Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
@@ -74,6 +99,7 @@ fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: true,
+            minimal_versions: false,
         }),
     }
     .run()
@@ -93,6 +119,7 @@ fn run_install_test(name: &str) -> TempProject {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: false,
+            minimal_versions: false,
         }),
     };
 
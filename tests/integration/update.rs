@@ -145,6 +145,8 @@ fn run_update_with_specs(project: &TempProject, specs: Vec<PackageSpec>) -> anyh
         },
         subcommand: Subcommand::Update(UpdateSubcommand {
             project_path: project.path().to_owned(),
+            pre: false,
+            minimal_versions: false,
             package_specs: specs,
         }),
     }
@@ -0,0 +1,175 @@
+//! Keeps an append-only record of every change made to the registry, since
+//! the index's Git history attributes everything to the registry itself.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use fs_err::{File, OpenOptions};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::auth::WriteAccess;
+
+/// Who made a change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AuditActor {
+    ApiKey,
+    Github { login: String, id: u64 },
+}
+
+impl From<&WriteAccess> for AuditActor {
+    fn from(access: &WriteAccess) -> Self {
+        match access {
+            WriteAccess::ApiKey => AuditActor::ApiKey,
+            WriteAccess::Github(github_info) => AuditActor::Github {
+                login: github_info.login().to_owned(),
+                id: *github_info.id(),
+            },
+        }
+    }
+}
+
+/// What was changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AuditAction {
+    Publish {
+        package: String,
+    },
+    AddScopeOwner {
+        scope: String,
+        owner_id: u64,
+    },
+    Deprecate {
+        package: String,
+        versions: Option<String>,
+    },
+}
+
+impl AuditAction {
+    /// The name of this kind of action, as used in its serialized form.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Publish { .. } => "publish",
+            AuditAction::AddScopeOwner { .. } => "add-scope-owner",
+            AuditAction::Deprecate { .. } => "deprecate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the change was made, in seconds since the Unix epoch.
+    pub time: i64,
+    pub actor: AuditActor,
+    pub action: AuditAction,
+
+    /// The address the request came from, if it's known.
+    pub ip: Option<IpAddr>,
+}
+
+/// Narrows down which audit log entries are returned.
+#[derive(Debug, Default)]
+pub struct AuditQuery {
+    /// Only entries made by the GitHub user with this login.
+    pub login: Option<String>,
+
+    /// Only entries for this kind of action, like `publish`.
+    pub action: Option<String>,
+
+    /// Only entries made at or after this time, in seconds since the epoch.
+    pub since: Option<i64>,
+}
+
+pub struct AuditLog {
+    /// Where the log is persisted as JSON lines. If this is `None`, the log is
+    /// only kept in memory and is lost when the registry restarts.
+    path: Option<PathBuf>,
+
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Load the audit log from the given file, starting a new one if the file
+    /// does not exist yet.
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+
+        if let Some(path) = &path {
+            if path.exists() {
+                let file = BufReader::new(File::open(path)?);
+
+                for (number, line) in file.lines().enumerate() {
+                    let entry = serde_json::from_str(&line?).with_context(|| {
+                        format!("could not parse line {} of {}", number + 1, path.display())
+                    })?;
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn record(
+        &self,
+        actor: AuditActor,
+        action: AuditAction,
+        ip: Option<IpAddr>,
+    ) -> anyhow::Result<()> {
+        let entry = AuditEntry {
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            actor,
+            action,
+            ip,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(path) = &self.path {
+            let mut line = serde_json::to_string(&entry)?;
+            line.push('\n');
+
+            let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+            file.write_all(line.as_bytes())?;
+        }
+
+        entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Find entries matching the query, most recent first.
+    pub fn query(&self, query: &AuditQuery, limit: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap();
+
+        entries
+            .iter()
+            .rev()
+            .filter(|entry| match &query.login {
+                Some(login) => match &entry.actor {
+                    AuditActor::Github { login: actor, .. } => actor.eq_ignore_ascii_case(login),
+                    AuditActor::ApiKey => false,
+                },
+                None => true,
+            })
+            .filter(|entry| match &query.action {
+                Some(action) => entry.action.name() == action,
+                None => true,
+            })
+            .filter(|entry| match query.since {
+                Some(since) => entry.time >= since,
+                None => true,
+            })
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, fmt, time::Duration};
 
 use anyhow::{anyhow, format_err};
 use constant_time_eq::constant_time_eq;
 use libwally::{package_id::PackageId, package_index::PackageIndex};
+use moka::sync::Cache;
 use reqwest::{Client, StatusCode};
 use rocket::{
     http::Status,
@@ -10,6 +11,7 @@ use rocket::{
     Request, State,
 };
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::error::Error;
 use crate::{config::Config, error::ApiErrorStatus};
@@ -27,11 +29,23 @@ pub enum AuthMode {
         client_id: String,
         #[serde(rename = "client-secret")]
         client_secret: String,
+        /// Where to find the GitHub API, for GitHub Enterprise. Defaults to
+        /// `https://api.github.com`.
+        #[serde(rename = "api-url", default)]
+        api_url: Option<Url>,
     },
     Unauthenticated,
 }
 
-#[derive(Deserialize)]
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
+/// How long a verified GitHub token is trusted before it's checked again.
+const TOKEN_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The most verified GitHub tokens we remember at once.
+const TOKEN_CACHE_CAPACITY: u64 = 10_000;
+
+#[derive(Clone, Deserialize)]
 pub struct GithubInfo {
     login: String,
     id: u64,
@@ -90,10 +104,72 @@ fn match_api_key<T>(request: &Request<'_>, key: &str, result: T) -> Outcome<T, E
     }
 }
 
+/// Remembers which GitHub user each recently verified token belongs to, so
+/// that we don't have to ask GitHub on every request. Tokens are keyed by
+/// their hash so that we don't hold onto them.
+pub struct GithubTokenCache {
+    users: Cache<[u8; 32], GithubInfo>,
+}
+
+impl Default for GithubTokenCache {
+    fn default() -> Self {
+        Self {
+            users: Cache::builder()
+                .max_capacity(TOKEN_CACHE_CAPACITY)
+                .time_to_live(TOKEN_CACHE_TTL)
+                .build(),
+        }
+    }
+}
+
+fn hash_token(token: &str) -> [u8; 32] {
+    *blake3::hash(token.as_bytes()).as_bytes()
+}
+
+/// The bearer token a request was made with, if it's already known to be
+/// valid. GitHub tokens only count once they've been verified and cached, so
+/// this never has to ask GitHub.
+pub async fn known_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    let token = match request.headers().get_one("authorization") {
+        Some(key) if key.starts_with("Bearer ") => key[6..].trim(),
+        _ => return None,
+    };
+
+    let config = request
+        .guard::<&State<Config>>()
+        .await
+        .expect("AuthMode was not configured");
+
+    let is_key = |key: &str| constant_time_eq(key.as_bytes(), token.as_bytes());
+
+    let known = match &config.auth {
+        AuthMode::Unauthenticated => false,
+        AuthMode::ApiKey(key) => is_key(key),
+        AuthMode::DoubleApiKey { read, write } => {
+            is_key(write) || read.as_deref().map_or(false, is_key)
+        }
+        AuthMode::GithubOAuth { .. } => {
+            let cache = request
+                .guard::<&State<GithubTokenCache>>()
+                .await
+                .expect("GithubTokenCache was not configured");
+
+            cache.users.contains_key(&hash_token(token))
+        }
+    };
+
+    if known {
+        Some(token)
+    } else {
+        None
+    }
+}
+
 async fn verify_github_token(
     request: &Request<'_>,
     client_id: &str,
     client_secret: &str,
+    api_url: Option<&Url>,
 ) -> Outcome<WriteAccess, Error> {
     let token: String = match request.headers().get_one("authorization") {
         Some(key) if key.starts_with("Bearer ") => (key[6..].trim()).to_owned(),
@@ -104,12 +180,49 @@ async fn verify_github_token(
         }
     };
 
+    let cache = request
+        .guard::<&State<GithubTokenCache>>()
+        .await
+        .expect("GithubTokenCache was not configured");
+    let token_hash = hash_token(&token);
+
+    if let Some(github_info) = cache.users.get(&token_hash) {
+        return Outcome::Success(WriteAccess::Github(github_info));
+    }
+
+    let outcome = check_github_token(&token, client_id, client_secret, api_url).await;
+
+    match &outcome {
+        Outcome::Success(WriteAccess::Github(github_info)) => {
+            cache.users.insert(token_hash, github_info.clone());
+        }
+        // Make sure a revoked token is forgotten even if it was cached again by
+        // a concurrent request that raced with this one.
+        Outcome::Failure((status, _)) if *status == Status::Unauthorized => {
+            cache.users.invalidate(&token_hash)
+        }
+        _ => {}
+    }
+
+    outcome
+}
+
+async fn check_github_token(
+    token: &str,
+    client_id: &str,
+    client_secret: &str,
+    api_url: Option<&Url>,
+) -> Outcome<WriteAccess, Error> {
+    let api_url = api_url
+        .map_or(DEFAULT_GITHUB_API_URL, |url| url.as_str())
+        .trim_end_matches('/');
+
     let client = Client::new();
     let response = client
-        .get("https://api.github.com/user")
+        .get(format!("{}/user", api_url))
         .header("accept", "application/json")
         .header("user-agent", "wally")
-        .bearer_auth(&token)
+        .bearer_auth(token)
         .send()
         .await;
 
@@ -117,6 +230,11 @@ async fn verify_github_token(
         Err(err) => {
             return format_err!(err).status(Status::InternalServerError).into();
         }
+        Ok(response) if response.status() == StatusCode::UNAUTHORIZED => {
+            return format_err!("Github auth was invalid")
+                .status(Status::Unauthorized)
+                .into();
+        }
         Ok(response) => match response.json::<GithubInfo>().await {
             Err(err) => {
                 return format_err!("Github auth failed: {}", err)
@@ -128,13 +246,10 @@ async fn verify_github_token(
     };
 
     let mut body = HashMap::new();
-    body.insert("access_token", &token);
+    body.insert("access_token", token);
 
     let response = client
-        .post(format!(
-            "https://api.github.com/applications/{}/token",
-            client_id
-        ))
+        .post(format!("{}/applications/{}/token", api_url, client_id))
         .header("accept", "application/json")
         .header("user-agent", "wally")
         .basic_auth(client_id, Some(client_secret))
@@ -207,13 +322,21 @@ pub enum WriteAccess {
 }
 
 impl WriteAccess {
+    /// Whether this access covers the whole registry rather than only the
+    /// scopes a user owns, which is required for admin operations.
+    pub fn is_admin(&self) -> bool {
+        matches!(self, WriteAccess::ApiKey)
+    }
+
     pub fn can_write_package(
         &self,
         package_id: &PackageId,
         index: &PackageIndex,
     ) -> anyhow::Result<bool> {
-        let scope = package_id.name().scope();
+        self.can_write_scope(package_id.name().scope(), index)
+    }
 
+    pub fn can_write_scope(&self, scope: &str, index: &PackageIndex) -> anyhow::Result<bool> {
         let has_permission = match self {
             WriteAccess::ApiKey => true,
             WriteAccess::Github(github_info) => {
@@ -253,7 +376,8 @@ impl<'r> FromRequest<'r> for WriteAccess {
             AuthMode::GithubOAuth {
                 client_id,
                 client_secret,
-            } => verify_github_token(request, client_id, client_secret).await,
+                api_url,
+            } => verify_github_token(request, client_id, client_secret, api_url.as_ref()).await,
         }
     }
 }
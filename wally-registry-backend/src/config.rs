@@ -1,8 +1,14 @@
+use std::path::PathBuf;
+
+use rocket::data::ByteUnit;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::{auth::AuthMode, storage::StorageMode};
+use crate::{
+    auth::AuthMode, cors::CorsConfig, mirror::MirrorConfig, rate_limit::RateLimitConfig,
+    storage::StorageMode,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
@@ -22,4 +28,36 @@ pub struct Config {
 
     /// The minimum wally cli version required to publish to the registry
     pub minimum_wally_version: Option<Version>,
+
+    /// Where package download statistics should be stored. If not specified,
+    /// download counts are only kept in memory and reset on restart.
+    pub stats_path: Option<PathBuf>,
+
+    /// Where the search index should be stored. If not specified, the search
+    /// index is kept in memory and rebuilt from the package index on startup.
+    pub search_index_path: Option<PathBuf>,
+
+    /// An upstream registry to act as a pull-through cache for. Packages that
+    /// aren't in our own index are fetched from it on demand.
+    pub mirror: Option<MirrorConfig>,
+
+    /// The largest package that can be published. If not specified, the limit
+    /// advertised in the package index's `config.json` is used, or 2 MiB if
+    /// there isn't one. This should match what the index advertises, since
+    /// that's what the CLI checks before uploading.
+    pub max_package_size: Option<ByteUnit>,
+
+    /// Where the audit log of changes made through the registry should be
+    /// stored. If not specified, the audit log is only kept in memory.
+    pub audit_log_path: Option<PathBuf>,
+
+    /// How many requests each client can make to the publish, search and
+    /// download routes. Routes without a budget aren't limited.
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Which websites can use the registry API from a browser. By default,
+    /// any website can read from the registry.
+    #[serde(default)]
+    pub cors: CorsConfig,
 }
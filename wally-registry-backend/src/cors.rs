@@ -0,0 +1,162 @@
+//! Decides which websites are allowed to use the registry API from a browser.
+//!
+//! Preflight requests are answered here rather than by a route, so that only
+//! origins, methods and headers that are actually allowed get a go-ahead.
+
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+use serde::{Deserialize, Serialize};
+
+/// Matches any origin, method or header.
+const WILDCARD: &str = "*";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct CorsConfig {
+    /// Origins that may call the registry, like `https://wally.example.com`.
+    pub allowed_origins: Vec<String>,
+
+    /// Methods that browsers may use in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers that browsers may send in cross-origin requests.
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![String::from(WILDCARD)],
+            allowed_methods: vec![String::from("GET")],
+            allowed_headers: vec![String::from(WILDCARD)],
+        }
+    }
+}
+
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Self { config }
+    }
+
+    /// The value to send back in `Access-Control-Allow-Origin`, if the origin
+    /// of this request is allowed.
+    fn allow_origin(&self, request: &Request<'_>) -> Option<String> {
+        if allows_wildcard(&self.config.allowed_origins) {
+            return Some(String::from(WILDCARD));
+        }
+
+        let origin = request.headers().get_one("Origin")?;
+        self.config
+            .allowed_origins
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(origin))
+            .map(|_| origin.to_owned())
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        allows(&self.config.allowed_methods, method)
+    }
+
+    fn allows_headers(&self, headers: Option<&str>) -> bool {
+        headers.map_or(true, |headers| {
+            headers
+                .split(',')
+                .map(str::trim)
+                .filter(|header| !header.is_empty())
+                .all(|header| allows(&self.config.allowed_headers, header))
+        })
+    }
+
+    fn preflight(&self, request: &Request<'_>, response: &mut Response<'_>) {
+        // Whatever the router made of this request doesn't matter, preflight
+        // responses never have a body.
+        response.set_sized_body(0, Cursor::new(""));
+        response.remove_header("Content-Type");
+
+        let requested_method = request
+            .headers()
+            .get_one("Access-Control-Request-Method")
+            .unwrap_or_default();
+        let requested_headers = request.headers().get_one("Access-Control-Request-Headers");
+
+        let allow_origin = match self.allow_origin(request) {
+            Some(origin)
+                if self.allows_method(requested_method)
+                    && self.allows_headers(requested_headers) =>
+            {
+                origin
+            }
+            _ => {
+                response.set_status(Status::Forbidden);
+                return;
+            }
+        };
+
+        response.set_status(Status::NoContent);
+        self.set_origin(response, allow_origin);
+        response.set_header(Header::new(
+            "Access-Control-Allow-Methods",
+            self.config.allowed_methods.join(", "),
+        ));
+        response.set_header(Header::new(
+            "Access-Control-Allow-Headers",
+            self.config.allowed_headers.join(", "),
+        ));
+    }
+
+    fn set_origin(&self, response: &mut Response<'_>, allow_origin: String) {
+        // Responses for specific origins differ between origins, so caches
+        // need to keep them apart.
+        if allow_origin != WILDCARD {
+            response.set_header(Header::new("Vary", "Origin"));
+        }
+
+        response.set_header(Header::new("Access-Control-Allow-Origin", allow_origin));
+    }
+}
+
+fn allows_wildcard(allowed: &[String]) -> bool {
+    allowed.iter().any(|allowed| allowed == WILDCARD)
+}
+
+fn allows(allowed: &[String], value: &str) -> bool {
+    allows_wildcard(allowed)
+        || allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(value))
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add CORS headers to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let is_preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+
+        if is_preflight {
+            self.preflight(request, response);
+            return;
+        }
+
+        if let Some(allow_origin) = self.allow_origin(request) {
+            self.set_origin(response, allow_origin);
+            response.set_header(Header::new(
+                "Access-Control-Expose-Headers",
+                "Wally-Total-Count",
+            ));
+        }
+    }
+}
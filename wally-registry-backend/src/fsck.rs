@@ -0,0 +1,120 @@
+//! Checks that the package index and the storage backend agree with each other.
+//!
+//! Publishing writes to storage before the index, so a failure in between can
+//! leave packages that exist in only one of the two. This walks both and
+//! reports anything that doesn't line up.
+
+use std::io::Cursor;
+
+use libwally::{manifest::Manifest, package_id::PackageId, package_index::PackageIndex};
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+use zip::ZipArchive;
+
+use crate::get_manifest;
+use crate::storage::{self, StorageBackend};
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    /// How many package versions in the index were checked.
+    pub checked: usize,
+
+    /// Package versions in the index with no contents in storage.
+    pub missing: Vec<PackageId>,
+
+    /// Package versions whose stored contents don't match their index entry.
+    pub invalid: Vec<InvalidPackage>,
+
+    /// Package versions in storage with no entry in the index.
+    pub orphaned: Vec<PackageId>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.invalid.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidPackage {
+    /// The package version, or just the package name if its whole index entry
+    /// couldn't be read.
+    pub package: String,
+    pub reason: String,
+}
+
+/// Check every package in the index against storage, optionally only those in
+/// the given scope.
+pub async fn check(
+    storage: &dyn StorageBackend,
+    index: &PackageIndex,
+    scope: Option<&str>,
+) -> anyhow::Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    for package_name in index.package_names()? {
+        if scope.map_or(false, |scope| scope != package_name.scope()) {
+            continue;
+        }
+
+        let metadata = match index.get_package_metadata(&package_name) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                report.invalid.push(InvalidPackage {
+                    package: package_name.to_string(),
+                    reason: format!("{:#}", err),
+                });
+                continue;
+            }
+        };
+
+        for manifest in &metadata.versions {
+            let package_id = manifest.package_id();
+            report.checked += 1;
+
+            if !storage.exists(&package_id).await? {
+                report.missing.push(package_id);
+                continue;
+            }
+
+            let stored_manifest = match read_manifest(storage, &package_id).await {
+                Ok(stored_manifest) => stored_manifest,
+                Err(err) => {
+                    report.invalid.push(InvalidPackage {
+                        package: package_id.to_string(),
+                        reason: format!("{:#}", err),
+                    });
+                    continue;
+                }
+            };
+
+            // Manifests don't implement PartialEq, but their serialized form is
+            // exactly what ends up in the index.
+            if serde_json::to_value(&stored_manifest)? != serde_json::to_value(manifest)? {
+                report.invalid.push(InvalidPackage {
+                    package: package_id.to_string(),
+                    reason: String::from("stored manifest does not match the index entry"),
+                });
+            }
+        }
+    }
+
+    report.orphaned = storage::orphaned_packages(storage, index, scope).await?;
+
+    Ok(report)
+}
+
+async fn read_manifest(
+    storage: &dyn StorageBackend,
+    package_id: &PackageId,
+) -> anyhow::Result<Manifest> {
+    let mut contents = Vec::new();
+    storage
+        .read(package_id)
+        .await?
+        .read_to_end(&mut contents)
+        .await?;
+
+    let mut archive = ZipArchive::new(Cursor::new(contents))?;
+    get_manifest(&mut archive)
+}
@@ -1,10 +1,17 @@
 #[macro_use]
 extern crate rocket;
 
+mod audit;
 mod auth;
 mod config;
+mod cors;
 mod error;
+mod fsck;
+mod metrics;
+mod mirror;
+mod rate_limit;
 mod search;
+mod stats;
 mod storage;
 
 #[cfg(test)]
@@ -12,46 +19,59 @@ mod tests;
 
 use std::convert::TryInto;
 use std::io::{Cursor, Read, Seek};
-use std::sync::RwLock;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
-use anyhow::{format_err, Context};
+use anyhow::{ensure, format_err, Context};
 use figment::{
     providers::{Env, Format, Toml},
     Figment,
 };
 use libwally::{
-    manifest::{Manifest, MANIFEST_FILE_NAME},
+    manifest::{Dependency, Manifest, MANIFEST_FILE_NAME},
     package_id::PackageId,
-    package_index::PackageIndex,
+    package_index::{Deprecation, PackageIndex},
     package_name::PackageName,
 };
-use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::stream::ReaderStream;
 use rocket::serde::json::Json;
 use rocket::{
-    data::{Data, ToByteUnit},
+    data::{ByteUnit, Data, ToByteUnit},
     fairing::AdHoc,
     http::{ContentType, Status},
     response::content,
     State,
 };
-use rocket::{Build, Request, Response};
+use rocket::{Build, Request};
 use semver::Version;
 use serde_json::json;
 use storage::StorageMode;
+use time::OffsetDateTime;
+use tokio::io::AsyncReadExt;
 use zip::ZipArchive;
 
-use crate::auth::{ReadAccess, WriteAccess};
+use crate::audit::{AuditAction, AuditActor, AuditLog, AuditQuery};
+use crate::auth::{GithubTokenCache, ReadAccess, WriteAccess};
 use crate::config::Config;
+use crate::cors::Cors;
 use crate::error::{ApiErrorContext, ApiErrorStatus, Error};
-use crate::search::SearchBackend;
+use crate::metrics::{Metrics, RequestMetrics};
+use crate::mirror::Mirror;
+use crate::rate_limit::RateLimiter;
+use crate::search::{SearchBackend, SearchOptions};
+use crate::stats::DownloadStats;
 use crate::storage::{GcsStorage, LocalStorage, StorageBackend, StorageOutput};
 
 #[cfg(feature = "s3-storage")]
 use crate::storage::S3Storage;
 
+/// The first release of Wally that can parse dependencies written as tables.
+const MIN_TABLE_DEPENDENCY_VERSION: &str = "0.4.0";
+
 #[get("/")]
 fn root() -> content::RawJson<serde_json::Value> {
     content::RawJson(json!({
@@ -59,9 +79,69 @@ fn root() -> content::RawJson<serde_json::Value> {
     }))
 }
 
+#[get("/health")]
+async fn health(
+    storage: &State<Box<dyn StorageBackend>>,
+    index: &State<PackageIndex>,
+    search_backend: &State<RwLock<SearchBackend>>,
+) -> (Status, Json<serde_json::Value>) {
+    fn check<T>(result: anyhow::Result<T>) -> serde_json::Value {
+        match result {
+            Ok(_) => json!("ok"),
+            Err(err) => json!(format!("{:#}", err)),
+        }
+    }
+
+    // Asking about a package that can't exist tells us whether the storage
+    // backend is reachable without downloading anything.
+    let probe = PackageId::new(
+        PackageName::new("wally", "health-check").unwrap(),
+        Version::new(0, 0, 0),
+    );
+    let storage_check = check(storage.exists(&probe).await);
+    let index_check = check(index.head_commit());
+    let search_check = check(match search_backend.read() {
+        Ok(search_backend) => Ok(search_backend.num_docs()),
+        Err(_) => Err(format_err!("search backend lock is poisoned")),
+    });
+
+    let healthy = [&storage_check, &index_check, &search_check]
+        .iter()
+        .all(|check| **check == json!("ok"));
+    let status = if healthy {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    (
+        status,
+        Json(json!({
+            "status": if healthy { "ok" } else { "unhealthy" },
+            "checks": {
+                "index": index_check,
+                "storage": storage_check,
+                "search": search_check,
+            },
+        })),
+    )
+}
+
+#[get("/metrics")]
+fn prometheus_metrics(
+    storage: &State<Box<dyn StorageBackend>>,
+    metrics: &State<Arc<Metrics>>,
+) -> (ContentType, String) {
+    (ContentType::Plain, metrics.render(storage.cache_stats()))
+}
+
 #[get("/v1/package-contents/<scope>/<name>/<version>")]
 async fn package_contents(
     storage: &State<Box<dyn StorageBackend>>,
+    index: &State<PackageIndex>,
+    mirror: &State<Option<Mirror>>,
+    stats: &State<DownloadStats>,
+    metrics: &State<Arc<Metrics>>,
     _read: Result<ReadAccess, Error>,
     scope: String,
     name: String,
@@ -80,14 +160,156 @@ async fn package_contents(
         .status(Status::BadRequest)?;
     let package_id = PackageId::new(package_name, version);
 
-    match storage.read(&package_id).await.map(ReaderStream::one) {
-        Ok(stream) => Ok((ContentType::GZIP, stream)),
-        Err(e) => Err(e).status(Status::NotFound),
+    let output = match storage.read(&package_id).await {
+        Ok(output) => output,
+        Err(err) => match mirror.inner() {
+            // Packages in our own index are never fetched from upstream, even
+            // if they're missing from storage.
+            Some(mirror) if index.get_package_metadata(package_id.name()).is_err() => {
+                mirror_package(storage.inner().as_ref(), mirror, &package_id)
+                    .await
+                    .status(Status::NotFound)?
+            }
+            _ => return Err(err).status(Status::NotFound),
+        },
+    };
+
+    stats.record_download(&package_id);
+
+    let output: StorageOutput = Box::new(metrics.record_download(output));
+
+    Ok((ContentType::GZIP, ReaderStream::one(output)))
+}
+
+/// Fetch a package we don't have from the upstream registry and keep a copy of
+/// it in storage for next time.
+async fn mirror_package(
+    storage: &dyn StorageBackend,
+    mirror: &Mirror,
+    package_id: &PackageId,
+) -> anyhow::Result<StorageOutput> {
+    let contents = mirror.fetch_package(package_id).await?;
+
+    let mut archive = ZipArchive::new(Cursor::new(contents.as_slice()))
+        .context("upstream registry returned an invalid archive")?;
+    let manifest = get_manifest(&mut archive)?;
+
+    ensure!(
+        &manifest.package_id() == package_id,
+        "upstream registry returned {} instead of {}",
+        manifest.package_id(),
+        package_id
+    );
+
+    if let Err(err) = storage.write(package_id, &contents).await {
+        println!("Could not cache mirrored package {}: {:?}", package_id, err);
     }
+
+    Ok(Box::new(Cursor::new(contents)))
+}
+
+#[get("/v1/package-readme/<scope>/<name>/<version>")]
+async fn package_readme(
+    storage: &State<Box<dyn StorageBackend>>,
+    index: &State<PackageIndex>,
+    _read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<(ContentType, String), Error> {
+    _read?;
+
+    let package_name = PackageName::new(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)?;
+    let version: Version = version
+        .parse()
+        .context("error parsing version")
+        .status(Status::BadRequest)?;
+    let package_id = PackageId::new(package_name, version);
+
+    let metadata = index
+        .get_package_metadata(package_id.name())
+        .status(Status::NotFound)?;
+    let readme = metadata
+        .versions
+        .iter()
+        .find(|manifest| manifest.package_id() == package_id)
+        .ok_or_else(|| format_err!("package {} not found", package_id).status(Status::NotFound))?
+        .package
+        .readme
+        .clone()
+        .ok_or_else(|| {
+            format_err!("package {} has no readme", package_id).status(Status::NotFound)
+        })?;
+
+    let mut contents = Vec::new();
+    storage
+        .read(&package_id)
+        .await
+        .status(Status::NotFound)?
+        .read_to_end(&mut contents)
+        .await
+        .context("could not read package from storage")?;
+
+    let mut archive = ZipArchive::new(Cursor::new(contents))
+        .context("package in storage is not a valid archive")?;
+    let readme = get_readme(&mut archive, &readme)?;
+
+    Ok((ContentType::new("text", "markdown"), readme))
 }
 
 #[get("/v1/package-metadata/<scope>/<name>")]
 async fn package_info(
+    index: &State<PackageIndex>,
+    mirror: &State<Option<Mirror>>,
+    _read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    _read?;
+
+    let package_name = PackageName::new(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)?;
+
+    let metadata = match (index.get_package_metadata(&package_name), mirror.inner()) {
+        (Ok(metadata), _) => metadata,
+        (Err(_), Some(mirror)) => mirror
+            .package_metadata(&package_name)
+            .status(Status::NotFound)?,
+        (Err(err), None) => return Err(err.into()),
+    };
+
+    Ok(Json(serde_json::to_value(&*metadata)?))
+}
+
+#[get("/v1/package-stats/<scope>/<name>")]
+async fn package_stats(
+    index: &State<PackageIndex>,
+    stats: &State<DownloadStats>,
+    _read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    _read?;
+
+    let package_name = PackageName::new(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)?;
+
+    index
+        .get_package_metadata(&package_name)
+        .status(Status::NotFound)?;
+
+    let package_stats = stats.package_stats(&package_name);
+
+    Ok(Json(serde_json::to_value(package_stats)?))
+}
+
+#[get("/v1/package-dependents/<scope>/<name>")]
+async fn package_dependents(
+    search_backend: &State<RwLock<SearchBackend>>,
     index: &State<PackageIndex>,
     _read: Result<ReadAccess, Error>,
     scope: String,
@@ -99,22 +321,65 @@ async fn package_info(
         .context("error parsing package name")
         .status(Status::BadRequest)?;
 
-    let metadata = &*index.get_package_metadata(&package_name)?;
+    // The package itself doesn't need to be in our index, since packages here
+    // may depend on packages from other registries.
+    if let Ok(search_backend) = search_backend.read() {
+        let dependents = search_backend.dependents(index, &package_name)?;
+        Ok(Json(serde_json::to_value(dependents)?))
+    } else {
+        Err(
+            format_err!("Unexpected error looking up dependents. Try again later.")
+                .status(Status::InternalServerError),
+        )
+    }
+}
 
-    Ok(Json(serde_json::to_value(metadata)?))
+/// Search results are returned as a plain list for compatibility with older
+/// clients, with the total number of matches in a header.
+#[derive(Responder)]
+struct SearchResponse {
+    results: Json<serde_json::Value>,
+    total: Header<'static>,
 }
 
-#[get("/v1/package-search?<query>")]
+#[get("/v1/package-search?<query>&<sort>&<offset>&<limit>")]
 async fn package_search(
     search_backend: &State<RwLock<SearchBackend>>,
+    stats: &State<DownloadStats>,
+    metrics: &State<Arc<Metrics>>,
     _read: Result<ReadAccess, Error>,
     query: String,
-) -> Result<Json<serde_json::Value>, Error> {
+    sort: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<SearchResponse, Error> {
     _read?;
 
+    let mut options = SearchOptions::default();
+
+    if let Some(sort) = sort {
+        options.sort = sort.parse().status(Status::BadRequest)?;
+    }
+
+    if let Some(offset) = offset {
+        options.offset = offset;
+    }
+
+    if let Some(limit) = limit {
+        options.limit = limit;
+    }
+
     if let Ok(search_backend) = search_backend.read() {
-        let result = search_backend.search(&query)?;
-        Ok(Json(serde_json::to_value(result)?))
+        let start = Instant::now();
+        let result = search_backend
+            .search(&query, &options, stats)
+            .status(Status::BadRequest)?;
+        metrics.record_search(start.elapsed());
+
+        Ok(SearchResponse {
+            results: Json(serde_json::to_value(result.docs)?),
+            total: Header::new("Wally-Total-Count", result.total.to_string()),
+        })
     } else {
         Err(
             format_err!("Unexpected error during search. Try again later.")
@@ -123,38 +388,167 @@ async fn package_search(
     }
 }
 
+#[get("/v1/admin/orphaned-packages?<scope>")]
+async fn orphaned_packages(
+    storage: &State<Box<dyn StorageBackend>>,
+    index: &State<PackageIndex>,
+    metrics: &State<Arc<Metrics>>,
+    authorization: Result<WriteAccess, Error>,
+    scope: Option<String>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+
+    if !authorization.is_admin() {
+        return Err(
+            format_err!("This operation requires an admin API key").status(Status::Forbidden)
+        );
+    }
+
+    update_index(index, metrics)?;
+
+    let orphans =
+        storage::orphaned_packages(storage.inner().as_ref(), index, scope.as_deref()).await?;
+
+    Ok(Json(serde_json::to_value(orphans)?))
+}
+
+#[get("/v1/admin/fsck?<scope>")]
+async fn consistency_check(
+    storage: &State<Box<dyn StorageBackend>>,
+    index: &State<PackageIndex>,
+    metrics: &State<Arc<Metrics>>,
+    authorization: Result<WriteAccess, Error>,
+    scope: Option<String>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+
+    if !authorization.is_admin() {
+        return Err(
+            format_err!("This operation requires an admin API key").status(Status::Forbidden)
+        );
+    }
+
+    update_index(index, metrics)?;
+
+    let report = fsck::check(storage.inner().as_ref(), index, scope.as_deref()).await?;
+
+    if !report.is_ok() {
+        println!(
+            "Consistency check found {} missing, {} invalid and {} orphaned packages",
+            report.missing.len(),
+            report.invalid.len(),
+            report.orphaned.len()
+        );
+    }
+
+    Ok(Json(serde_json::to_value(report)?))
+}
+
+/// Held for the whole of a publish, from checking that the package doesn't
+/// exist yet to pushing it to the index, so that concurrent publishes can't
+/// both pass the check. Deprecating a package takes it too, since that also
+/// pushes to the index.
+#[derive(Default)]
+struct PublishLock(tokio::sync::Mutex<()>);
+
+#[get("/v1/admin/audit-log?<login>&<action>&<since>&<limit>")]
+async fn audit_log_entries(
+    audit_log: &State<AuditLog>,
+    authorization: Result<WriteAccess, Error>,
+    login: Option<String>,
+    action: Option<String>,
+    since: Option<i64>,
+    limit: Option<usize>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+
+    if !authorization.is_admin() {
+        return Err(
+            format_err!("This operation requires an admin API key").status(Status::Forbidden)
+        );
+    }
+
+    let query = AuditQuery {
+        login,
+        action,
+        since,
+    };
+    let entries = audit_log.query(&query, limit.unwrap_or(100));
+
+    Ok(Json(serde_json::to_value(entries)?))
+}
+
 #[post("/v1/publish", data = "<data>")]
 async fn publish(
     storage: &State<Box<dyn StorageBackend>>,
     search_backend: &State<RwLock<SearchBackend>>,
     index: &State<PackageIndex>,
+    config: &State<Config>,
+    metrics: &State<Arc<Metrics>>,
+    audit_log: &State<AuditLog>,
+    publish_lock: &State<PublishLock>,
     authorization: Result<WriteAccess, Error>,
-    _cli_version: Result<WallyVersion, Error>,
+    cli_version: Result<WallyVersion, Error>,
+    client_ip: Option<IpAddr>,
     data: Data<'_>,
 ) -> Result<Json<serde_json::Value>, Error> {
-    _cli_version?;
+    let cli_version = cli_version?;
     let authorization = authorization?;
+    let actor = AuditActor::from(&authorization);
 
-    let contents = data
-        .open(2.mebibytes())
-        .into_bytes()
+    let max_size = max_package_size(config, index);
+
+    // Packages can be large, so write them to disk as they arrive instead of
+    // holding them in memory.
+    let upload_dir = tempfile::tempdir().context("could not create upload directory")?;
+    let upload_path = upload_dir.path().join("package.zip");
+    let upload = data
+        .open(max_size)
+        .into_file(&upload_path)
         .await
         .context("could not read request body")?;
 
-    if !contents.is_complete() {
-        return Err(format_err!("request body too large").status(Status::BadRequest));
+    if !upload.is_complete() {
+        return Err(format_err!(
+            "package is larger than this registry's limit of {}",
+            max_size
+        )
+        .status(Status::PayloadTooLarge));
     }
 
-    let contents = Cursor::new(contents.value);
-    let mut archive = ZipArchive::new(contents)
+    let upload = upload.into_inner().into_std().await;
+    let mut archive = ZipArchive::new(upload)
         .context("could not read ZIP archive")
         .status(Status::BadRequest)?;
 
-    index.update()?;
+    let _publish_guard = publish_lock.0.lock().await;
+
+    update_index(index, metrics)?;
+
+    // Remember where the index was before we touch it, so that we can tell
+    // whether the search index has missed anything published elsewhere.
+    let previous_head = index.head_commit()?;
 
     let manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
     let package_id = manifest.package_id();
 
+    manifest
+        .validate_metadata()
+        .context("invalid package metadata")
+        .status(Status::BadRequest)?;
+
+    // The index entry keeps table-form dependencies as JSON objects, so once a
+    // package has one, clients older than MIN_TABLE_DEPENDENCY_VERSION can't
+    // read any version of that package. This only stops those clients from
+    // publishing such packages; it can't stop them from failing to read them.
+    if has_table_dependencies(&manifest) {
+        cli_version.require(&Version::parse(MIN_TABLE_DEPENDENCY_VERSION).unwrap())?;
+    }
+
+    if let Some(readme) = &manifest.package.readme {
+        get_readme(&mut archive, readme).status(Status::BadRequest)?;
+    }
+
     if !authorization.can_write_package(&package_id, &index)? {
         return Err(format_err!(
             "you do not have permission to write in scope {}",
@@ -170,39 +564,214 @@ async fn publish(
 
         if !index.is_scope_owner(scope, user_id)? {
             index.add_scope_owner(scope, user_id)?;
+            record_audit(
+                audit_log,
+                actor.clone(),
+                AuditAction::AddScopeOwner {
+                    scope: scope.to_owned(),
+                    owner_id: *user_id,
+                },
+                client_ip,
+            );
         }
     }
 
     let package_metadata = index.get_package_metadata(manifest.package_id().name());
+    let mut already_indexed = false;
 
     if let Ok(metadata) = package_metadata {
-        if metadata.versions.iter().any(|published_manifest| {
+        let published_manifest = metadata.versions.iter().find(|published_manifest| {
             published_manifest.package.version == manifest.package.version
-        }) {
-            return Err(format_err!("package already exists in index").status(Status::Conflict));
+        });
+
+        if let Some(published_manifest) = published_manifest {
+            // An earlier publish of this exact package may have made it into
+            // the index but failed before its contents were put in place. In
+            // that case we let it be published again to finish the job.
+            let same_manifest =
+                serde_json::to_value(published_manifest)? == serde_json::to_value(&manifest)?;
+
+            if !same_manifest || storage.exists(&package_id).await? {
+                return Err(format_err!("package already exists in index").status(Status::Conflict));
+            }
+
+            already_indexed = true;
         }
     }
 
+    // Contents are staged until the index has the package, so a failed push
+    // doesn't leave anything behind in storage that would block a retry.
     storage
-        .write(&manifest.package_id(), &archive.into_inner().into_inner())
+        .stage(&package_id, &upload_path)
         .await
         .context("could not write package to storage backend")?;
 
-    index
-        .publish(&manifest)
-        .context("could not publish package to index")?;
+    if !already_indexed {
+        if let Err(err) = index.publish(&manifest) {
+            if let Err(discard_err) = storage.discard(&package_id).await {
+                println!(
+                    "Could not clean up staged package {}: {:?}",
+                    package_id, discard_err
+                );
+            }
+
+            return Err(err.context("could not publish package to index").into());
+        }
+    }
+
+    storage
+        .promote(&package_id)
+        .await
+        .context("could not move package into place in storage backend")?;
 
     if let Ok(mut search_backend) = search_backend.try_write() {
-        // TODO: Recrawling the whole index for each publish is very wasteful!
-        // Eventually this will get too expensive and we should only add the new package.
-        search_backend.crawl_packages(&index)?;
+        if search_backend.indexed_commit() == Some(previous_head.as_str()) {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            search_backend.index_package(&index, &manifest.package.name, Some(now))?;
+        } else {
+            // Other registry instances have published packages that we haven't
+            // indexed yet, so we need to catch up on everything.
+            search_backend.crawl_packages(&index)?;
+        }
     }
 
+    metrics.record_publish();
+    record_audit(
+        audit_log,
+        actor,
+        AuditAction::Publish {
+            package: package_id.to_string(),
+        },
+        client_ip,
+    );
+
     Ok(Json(json!({
         "message": "Package published successfully!"
     })))
 }
 
+#[post("/v1/package-deprecate/<scope>/<name>", data = "<deprecation>")]
+async fn package_deprecate(
+    search_backend: &State<RwLock<SearchBackend>>,
+    index: &State<PackageIndex>,
+    metrics: &State<Arc<Metrics>>,
+    audit_log: &State<AuditLog>,
+    publish_lock: &State<PublishLock>,
+    authorization: Result<WriteAccess, Error>,
+    client_ip: Option<IpAddr>,
+    scope: String,
+    name: String,
+    deprecation: Json<Deprecation>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+    let deprecation = deprecation.into_inner();
+
+    let package_name = PackageName::new(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)?;
+
+    if deprecation.message.trim().is_empty() {
+        return Err(format_err!("a deprecation needs a message").status(Status::BadRequest));
+    }
+
+    let _publish_guard = publish_lock.0.lock().await;
+
+    update_index(index, metrics)?;
+    let previous_head = index.head_commit()?;
+
+    if !authorization.can_write_scope(package_name.scope(), index)? {
+        return Err(format_err!(
+            "you do not have permission to write in scope {}",
+            package_name.scope()
+        )
+        .status(Status::Unauthorized));
+    }
+
+    let metadata = index
+        .get_package_metadata(&package_name)
+        .status(Status::NotFound)?;
+
+    let covers_any = metadata
+        .versions
+        .iter()
+        .any(|manifest| deprecation.applies_to(&manifest.package.version));
+
+    if !covers_any {
+        return Err(format_err!(
+            "no published versions of {} match {}",
+            package_name,
+            deprecation
+                .versions
+                .as_ref()
+                .map_or_else(|| String::from("*"), |versions| versions.to_string())
+        )
+        .status(Status::BadRequest));
+    }
+
+    let versions = deprecation.versions.as_ref().map(ToString::to_string);
+
+    index
+        .deprecate(&package_name, deprecation)
+        .context("could not write deprecation to index")?;
+
+    if let Ok(mut search_backend) = search_backend.try_write() {
+        if search_backend.indexed_commit() == Some(previous_head.as_str()) {
+            // Deprecating a package doesn't count as updating it.
+            search_backend.index_package(&index, &package_name, None)?;
+        } else {
+            search_backend.crawl_packages(&index)?;
+        }
+    }
+
+    record_audit(
+        audit_log,
+        AuditActor::from(&authorization),
+        AuditAction::Deprecate {
+            package: package_name.to_string(),
+            versions,
+        },
+        client_ip,
+    );
+
+    Ok(Json(json!({
+        "message": "Package deprecated successfully!"
+    })))
+}
+
+/// Add an entry to the audit log. By the time we get here the change has
+/// already been made, so failing to record it only gets logged.
+fn record_audit(
+    audit_log: &AuditLog,
+    actor: AuditActor,
+    action: AuditAction,
+    client_ip: Option<IpAddr>,
+) {
+    if let Err(err) = audit_log.record(actor, action.clone(), client_ip) {
+        println!("Could not write to audit log: {:?} ({:?})", err, action);
+    }
+}
+
+/// Fetch the latest package index, keeping track of how long it takes.
+fn update_index(index: &PackageIndex, metrics: &Metrics) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let result = index.update();
+    metrics.record_index_update(start.elapsed());
+
+    result
+}
+
+/// The largest package that can be published, preferring the registry's own
+/// configuration over what the package index advertises.
+fn max_package_size(config: &Config, index: &PackageIndex) -> ByteUnit {
+    config
+        .max_package_size
+        .or_else(|| {
+            let index_config = index.config().ok()?;
+            Some(index_config.max_package_size?.bytes())
+        })
+        .unwrap_or_else(|| 2.mebibytes())
+}
+
 fn get_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<Manifest> {
     let mut manifest_file = archive
         .by_name(MANIFEST_FILE_NAME)
@@ -223,6 +792,37 @@ fn get_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<M
     Ok(manifest)
 }
 
+fn has_table_dependencies(manifest: &Manifest) -> bool {
+    manifest
+        .dependencies
+        .values()
+        .chain(manifest.server_dependencies.values())
+        .chain(manifest.dev_dependencies.values())
+        .any(|dependency| matches!(dependency, Dependency::Detailed(_)))
+}
+
+fn get_readme<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    readme: &Path,
+) -> anyhow::Result<String> {
+    // Archives always use forward slashes, whichever platform they came from.
+    let archive_name = readme
+        .to_str()
+        .context("readme path is not valid Unicode")?
+        .replace('\\', "/");
+
+    let mut readme_file = archive
+        .by_name(&archive_name)
+        .with_context(|| format!("could not find readme {} in package", archive_name))?;
+
+    let mut readme = String::new();
+    readme_file
+        .read_to_string(&mut readme)
+        .context("could not read readme")?;
+
+    Ok(readme)
+}
+
 pub fn server(figment: Figment) -> rocket::Rocket<Build> {
     let config: Config = figment.extract().expect("could not read configuration");
 
@@ -243,26 +843,84 @@ pub fn server(figment: Figment) -> rocket::Rocket<Build> {
     println!("Cloning package index repository...");
     let package_index = PackageIndex::new_temp(&config.index_url, config.github_token).unwrap();
 
+    // The CLI checks packages against the index's limit before uploading, so
+    // a different limit here would turn away packages or let them through
+    // inconsistently.
+    if let Some(max_size) = config.max_package_size {
+        let advertised = package_index
+            .config()
+            .ok()
+            .and_then(|index_config| index_config.max_package_size);
+
+        if advertised != Some(max_size.as_u64()) {
+            println!(
+                "Warning: max_package_size is {} but the package index advertises {}",
+                max_size,
+                advertised
+                    .map_or_else(|| String::from("no limit"), |size| size.bytes().to_string())
+            );
+        }
+    }
+
+    let mirror = config.mirror.as_ref().map(|mirror_config| {
+        println!("Mirroring upstream registry {}...", mirror_config.index_url);
+        Mirror::new(mirror_config).expect("could not set up mirror of upstream registry")
+    });
+
     println!("Initializing search backend...");
-    let search_backend = SearchBackend::new(&package_index).unwrap();
+    let search_backend =
+        SearchBackend::new(&package_index, config.search_index_path.as_deref()).unwrap();
+
+    match &config.stats_path {
+        Some(path) => println!("Loading download statistics from {}", path.display()),
+        None => println!("Download statistics will not be persisted"),
+    }
+    let download_stats = DownloadStats::open(config.stats_path).unwrap();
+
+    match &config.audit_log_path {
+        Some(path) => println!("Loading audit log from {}", path.display()),
+        None => println!("Audit log will not be persisted"),
+    }
+    let audit_log = AuditLog::open(config.audit_log_path).unwrap();
+
+    let metrics = Arc::new(Metrics::default());
+    let rate_limiter = RateLimiter::new(&config.rate_limit);
+    let cors = Cors::new(config.cors.clone());
 
     rocket::custom(figment)
         .mount(
             "/",
             routes![
                 root,
+                health,
+                prometheus_metrics,
                 package_contents,
                 publish,
+                package_deprecate,
+                package_readme,
                 package_info,
+                package_stats,
+                package_dependents,
                 package_search,
-                cors_options,
+                orphaned_packages,
+                consistency_check,
+                audit_log_entries,
+                rate_limit::rate_limited,
             ],
         )
         .manage(storage_backend)
         .manage(package_index)
+        .manage(mirror)
         .manage(RwLock::new(search_backend))
+        .manage(download_stats)
+        .manage(audit_log)
+        .manage(PublishLock::default())
+        .manage(GithubTokenCache::default())
+        .manage(Arc::clone(&metrics))
         .attach(AdHoc::config::<Config>())
-        .attach(Cors)
+        .attach(cors)
+        .attach(RequestMetrics(metrics))
+        .attach(rate_limiter)
 }
 
 fn configure_gcs(bucket: String, cache_size: Option<u64>) -> anyhow::Result<GcsStorage> {
@@ -303,31 +961,35 @@ fn configure_s3(bucket: String, cache_size: Option<u64>) -> anyhow::Result<S3Sto
     Ok(S3Storage::new(client, bucket, cache_size))
 }
 
-struct Cors;
+/// The version of Wally a client says it is, from its `Wally-Version` header.
+/// Requests from clients older than `minimum_wally_version` are turned away.
+struct WallyVersion(Option<Version>);
 
-#[rocket::async_trait]
-impl Fairing for Cors {
-    fn info(&self) -> Info {
-        Info {
-            name: "Add CORS headers to responses",
-            kind: Kind::Response,
-        }
-    }
+impl WallyVersion {
+    fn require(&self, minimum_version: &Version) -> Result<(), Error> {
+        let version = match &self.0 {
+            Some(version) => version,
+            None => {
+                return Err(format_err!(
+                    "Wally version header required. Try upgrading your wally installation."
+                )
+                .status(Status::UpgradeRequired));
+            }
+        };
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Methods", "GET"));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
+        if version < minimum_version {
+            Err(format_err!(
+                "This registry requires Wally {} (you are using {})",
+                minimum_version,
+                version
+            )
+            .status(Status::UpgradeRequired))
+        } else {
+            Ok(())
+        }
     }
 }
 
-#[options("/<_..>")]
-fn cors_options() -> Result<(), Error> {
-    Ok(())
-}
-
-struct WallyVersion;
-
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for WallyVersion {
     type Error = Error;
@@ -338,41 +1000,27 @@ impl<'r> FromRequest<'r> for WallyVersion {
             .await
             .expect("Failed to load config");
 
-        let minimum_version = match &config.minimum_wally_version {
-            Some(version) => version,
-            None => return Outcome::Success(WallyVersion),
-        };
-
         let version = match request.headers().get_one("Wally-Version") {
-            Some(version) => version,
-            None => {
-                return format_err!(
-                    "Wally version header required. Try upgrading your wally installation."
-                )
-                .status(Status::UpgradeRequired)
-                .into();
-            }
+            Some(version) => match Version::parse(version) {
+                Ok(version) => Some(version),
+                Err(err) if config.minimum_wally_version.is_some() => {
+                    return format_err!("Failed to parse wally version header: {}", err)
+                        .status(Status::BadRequest)
+                        .into();
+                }
+                Err(_) => None,
+            },
+            None => None,
         };
 
-        let version = match Version::parse(version) {
-            Ok(version) => version,
-            Err(err) => {
-                return format_err!("Failed to parse wally version header: {}", err)
-                    .status(Status::BadRequest)
-                    .into();
-            }
-        };
+        let version = WallyVersion(version);
 
-        if &version < minimum_version {
-            format_err!(
-                "This registry requires Wally {} (you are using {})",
-                minimum_version,
-                version
-            )
-            .status(Status::UpgradeRequired)
-            .into()
-        } else {
-            Outcome::Success(WallyVersion)
+        match &config.minimum_wally_version {
+            Some(minimum_version) => match version.require(minimum_version) {
+                Ok(()) => Outcome::Success(version),
+                Err(err) => err.into(),
+            },
+            None => Outcome::Success(version),
         }
     }
 }
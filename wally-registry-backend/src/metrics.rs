@@ -0,0 +1,328 @@
+//! Collects operational metrics and renders them in the Prometheus text
+//! exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::storage::CacheStats;
+
+/// Upper bounds of histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Requests handled, keyed by route, method and status code.
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    request_durations: Mutex<BTreeMap<String, Histogram>>,
+    publishes: AtomicU64,
+    downloads: AtomicU64,
+    download_bytes: Arc<AtomicU64>,
+    search_durations: Mutex<Histogram>,
+    index_update_durations: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, route: &str, method: &str, status: u16, duration: Duration) {
+        let key = (route.to_owned(), method.to_owned(), status);
+        *self.requests.lock().unwrap().entry(key).or_default() += 1;
+
+        self.request_durations
+            .lock()
+            .unwrap()
+            .entry(route.to_owned())
+            .or_default()
+            .observe(duration);
+    }
+
+    pub fn record_publish(&self) {
+        self.publishes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a download, wrapping the package contents so that the bytes sent
+    /// are counted as they're read.
+    pub fn record_download<R>(&self, contents: R) -> CountingReader<R> {
+        self.downloads.fetch_add(1, Ordering::Relaxed);
+
+        CountingReader {
+            inner: contents,
+            bytes: Arc::clone(&self.download_bytes),
+        }
+    }
+
+    pub fn record_search(&self, duration: Duration) {
+        self.search_durations.lock().unwrap().observe(duration);
+    }
+
+    pub fn record_index_update(&self, duration: Duration) {
+        self.index_update_durations
+            .lock()
+            .unwrap()
+            .observe(duration);
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn render(&self, cache_stats: Option<CacheStats>) -> String {
+        let mut output = String::new();
+
+        header(
+            &mut output,
+            "wally_http_requests_total",
+            "counter",
+            "HTTP requests handled, by route, method and status.",
+        );
+        for ((route, method, status), count) in self.requests.lock().unwrap().iter() {
+            writeln!(
+                output,
+                "wally_http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                escape(method),
+                status,
+                count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut output,
+            "wally_http_request_duration_seconds",
+            "histogram",
+            "Time taken to handle HTTP requests, by route.",
+        );
+        for (route, histogram) in self.request_durations.lock().unwrap().iter() {
+            let labels = format!("route=\"{}\"", escape(route));
+            histogram.render(&mut output, "wally_http_request_duration_seconds", &labels);
+        }
+
+        header(
+            &mut output,
+            "wally_publishes_total",
+            "counter",
+            "Packages published successfully.",
+        );
+        writeln!(
+            output,
+            "wally_publishes_total {}",
+            self.publishes.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        header(
+            &mut output,
+            "wally_downloads_total",
+            "counter",
+            "Package downloads served.",
+        );
+        writeln!(
+            output,
+            "wally_downloads_total {}",
+            self.downloads.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        header(
+            &mut output,
+            "wally_download_bytes_total",
+            "counter",
+            "Bytes of package contents sent to clients.",
+        );
+        writeln!(
+            output,
+            "wally_download_bytes_total {}",
+            self.download_bytes.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        header(
+            &mut output,
+            "wally_search_duration_seconds",
+            "histogram",
+            "Time taken to run package searches.",
+        );
+        self.search_durations.lock().unwrap().render(
+            &mut output,
+            "wally_search_duration_seconds",
+            "",
+        );
+
+        header(
+            &mut output,
+            "wally_index_update_duration_seconds",
+            "histogram",
+            "Time taken to fetch the latest package index.",
+        );
+        self.index_update_durations.lock().unwrap().render(
+            &mut output,
+            "wally_index_update_duration_seconds",
+            "",
+        );
+
+        if let Some(cache_stats) = cache_stats {
+            header(
+                &mut output,
+                "wally_storage_cache_hits_total",
+                "counter",
+                "Package reads served from the storage cache.",
+            );
+            writeln!(
+                output,
+                "wally_storage_cache_hits_total {}",
+                cache_stats.hits
+            )
+            .unwrap();
+
+            header(
+                &mut output,
+                "wally_storage_cache_misses_total",
+                "counter",
+                "Package reads that had to go to the storage backend.",
+            );
+            writeln!(
+                output,
+                "wally_storage_cache_misses_total {}",
+                cache_stats.misses
+            )
+            .unwrap();
+        }
+
+        output
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// How many observations fell into each bucket in `BUCKETS`. These aren't
+    /// cumulative; that's done when rendering.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        if let Some(index) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+
+        for (bound, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            writeln!(
+                output,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        )
+        .unwrap();
+
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        writeln!(output, "{}_sum{} {}", name, labels, self.sum).unwrap();
+        writeln!(output, "{}_count{} {}", name, labels, self.count).unwrap();
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Wraps package contents being downloaded and counts how many bytes are read
+/// from them.
+pub struct CountingReader<R> {
+    inner: R,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let read = buf.filled().len() - filled_before;
+            self.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
+
+/// Records how many requests each route handles and how long they take.
+pub struct RequestMetrics(pub Arc<Metrics>);
+
+/// When the current request started, stored in Rocket's request-local cache.
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Record request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+
+        // Label by the route's URI template rather than the real path, so that
+        // every package doesn't get its own series.
+        let route = request
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or_else(|| String::from("unmatched"));
+
+        self.0.record_request(
+            &route,
+            request.method().as_str(),
+            response.status().code,
+            start.0.elapsed(),
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Lets the registry act as a pull-through cache for another registry.
+//!
+//! Packages that aren't in this registry's own index are looked up in the
+//! upstream registry's index and downloaded from its API on demand. Downloaded
+//! archives are kept in our storage backend so that they can still be served
+//! when the upstream registry is unavailable.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use libwally::{
+    package_id::PackageId,
+    package_index::{PackageIndex, PackageMetadata},
+    package_name::PackageName,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long to wait between fetches of the upstream index. Misses inside this
+/// window are answered from our existing copy.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MirrorConfig {
+    /// The URL of the Git repository containing the upstream registry's
+    /// package index.
+    pub index_url: Url,
+}
+
+pub struct Mirror {
+    index: PackageIndex,
+    client: Client,
+    last_update: Mutex<Instant>,
+}
+
+impl Mirror {
+    pub fn new(config: &MirrorConfig) -> anyhow::Result<Self> {
+        let index = PackageIndex::new_temp(&config.index_url, None).with_context(|| {
+            format!(
+                "could not clone upstream package index {}",
+                config.index_url
+            )
+        })?;
+
+        Ok(Self {
+            index,
+            client: Client::new(),
+            last_update: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Look up a package in the upstream index, fetching the latest changes
+    /// first if we haven't done so recently.
+    pub fn package_metadata(&self, name: &PackageName) -> anyhow::Result<Arc<PackageMetadata>> {
+        self.update();
+
+        self.index
+            .get_package_metadata(name)
+            .with_context(|| format!("could not find package {} in upstream registry", name))
+    }
+
+    /// Download the contents of a package from the upstream registry's API.
+    pub async fn fetch_package(&self, package_id: &PackageId) -> anyhow::Result<Vec<u8>> {
+        let api = self.index.config()?.api;
+        let path = format!(
+            "/v1/package-contents/{}/{}/{}",
+            package_id.name().scope(),
+            package_id.name().name(),
+            package_id.version()
+        );
+
+        let response = self
+            .client
+            .get(api.join(&path)?)
+            .header("Wally-Version", VERSION)
+            .send()
+            .await
+            .with_context(|| format!("could not reach upstream registry {}", api))?;
+
+        if !response.status().is_success() {
+            bail!(
+                "upstream registry {} could not provide {}: {}",
+                api,
+                package_id,
+                response.status()
+            );
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    fn update(&self) {
+        let mut last_update = self.last_update.lock().unwrap();
+
+        if last_update.elapsed() < UPDATE_INTERVAL {
+            return;
+        }
+
+        // If the upstream registry is down we can keep serving whatever we
+        // fetched before, so this isn't fatal.
+        if let Err(err) = self.index.update() {
+            println!("Could not update upstream index: {:?}", err);
+        }
+
+        *last_update = Instant::now();
+    }
+}
@@ -0,0 +1,208 @@
+//! Limits how often each client can hit the more expensive routes.
+//!
+//! Requests are counted per API token once the token is known to be valid, or
+//! per IP address otherwise, in fixed windows. Requests over budget are rerouted to `rate_limited`
+//! before any guards run, so they never reach GitHub or the storage backend.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use moka::sync::Cache;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method};
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::{Data, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::auth::known_token;
+
+/// The most clients we keep track of per budget. Beyond this, the least
+/// recently seen clients are forgotten and start over with a fresh budget.
+const MAX_TRACKED_CLIENTS: u64 = 100_000;
+
+const RATE_LIMITED_PATH: &str = "/v1/rate-limited";
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    /// Budget for publishing packages.
+    pub publish: Option<RateLimit>,
+
+    /// Budget for searching for packages.
+    pub search: Option<RateLimit>,
+
+    /// Budget for downloading package contents.
+    pub download: Option<RateLimit>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimit {
+    /// How many requests each client can make per period.
+    pub requests: u32,
+
+    /// The length of a period in seconds.
+    pub period: u64,
+}
+
+/// How many requests a client has made in the current period.
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+struct Budget {
+    limit: RateLimit,
+    windows: Cache<u64, Arc<Mutex<Window>>>,
+}
+
+impl Budget {
+    fn new(limit: RateLimit) -> Self {
+        let period = Duration::from_secs(limit.period);
+
+        Self {
+            limit,
+            windows: Cache::builder()
+                .max_capacity(MAX_TRACKED_CLIENTS)
+                .time_to_idle(period)
+                .build(),
+        }
+    }
+
+    /// Count a request from the given client, returning how many seconds they
+    /// need to wait if they're over budget.
+    fn check(&self, client: u64) -> Option<u64> {
+        let period = Duration::from_secs(self.limit.period);
+        let now = Instant::now();
+
+        let window = self.windows.get_with(client, || {
+            Arc::new(Mutex::new(Window {
+                start: now,
+                count: 0,
+            }))
+        });
+        let mut window = window.lock().unwrap();
+
+        if now.duration_since(window.start) >= period {
+            window.start = now;
+            window.count = 0;
+        }
+
+        if window.count >= self.limit.requests {
+            let remaining = period - now.duration_since(window.start);
+
+            // Round up so that clients don't come back a moment too early.
+            return Some(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+        }
+
+        window.count += 1;
+        None
+    }
+}
+
+pub struct RateLimiter {
+    publish: Option<Budget>,
+    search: Option<Budget>,
+    download: Option<Budget>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            publish: config.publish.map(Budget::new),
+            search: config.search.map(Budget::new),
+            download: config.download.map(Budget::new),
+        }
+    }
+
+    fn budget_for(&self, request: &Request<'_>) -> Option<&Budget> {
+        let path = request.uri().path().as_str();
+
+        let budget = if request.method() == Method::Post && path == "/v1/publish" {
+            &self.publish
+        } else if path.starts_with("/v1/package-search") {
+            &self.search
+        } else if path.starts_with("/v1/package-contents/") {
+            &self.download
+        } else {
+            &None
+        };
+
+        budget.as_ref()
+    }
+}
+
+/// Identify who a request is from: their token if it's one we know to be
+/// valid, otherwise their IP address. Unverified tokens can't be trusted, since
+/// sending a new made-up token with every request would get a fresh budget
+/// each time. Tokens are hashed so that we don't hold onto them.
+async fn client_key(request: &Request<'_>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    match known_token(request).await {
+        Some(token) => token.hash(&mut hasher),
+        None => request.client_ip().hash(&mut hasher),
+    }
+
+    hasher.finish()
+}
+
+/// How long a rate limited request should wait, set before it's rerouted.
+#[derive(Default)]
+pub struct RetryAfter(Option<u64>);
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit requests",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let budget = match self.budget_for(request) {
+            Some(budget) => budget,
+            None => return,
+        };
+
+        if let Some(retry_after) = budget.check(client_key(request).await) {
+            request.local_cache(|| RetryAfter(Some(retry_after)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap());
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r RetryAfter {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(request.local_cache(RetryAfter::default))
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 429)]
+pub struct RateLimitedResponse {
+    body: Json<serde_json::Value>,
+    retry_after: Header<'static>,
+}
+
+#[get("/v1/rate-limited")]
+pub fn rate_limited(retry_after: &RetryAfter) -> RateLimitedResponse {
+    let seconds = retry_after.0.unwrap_or(1);
+
+    RateLimitedResponse {
+        body: Json(json!({
+            "message": format!("Too many requests, try again in {} seconds", seconds),
+        })),
+        retry_after: Header::new("Retry-After", seconds.to_string()),
+    }
+}
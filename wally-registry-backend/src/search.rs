@@ -1,9 +1,17 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Instant;
 
-use libwally::package_index::PackageIndex;
+use anyhow::bail;
+
+use libwally::manifest::Realm;
+use libwally::package_id::PackageId;
+use libwally::package_index::{Deprecation, PackageIndex, PackageMetadata};
 use libwally::package_name::PackageName;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use libwally::package_req::PackageReq;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, TermQuery};
 
 use serde::{Deserialize, Serialize};
 use tantivy::tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer};
@@ -11,17 +19,31 @@ use tantivy::{schema::*, IndexReader, ReloadPolicy};
 use tantivy::{Index, IndexWriter};
 use walkdir::{DirEntry, WalkDir};
 
+use crate::stats::DownloadStats;
+
 static DOC_LIMIT: usize = 100;
 
+/// Filters that can be written into a search query as `key:value`.
+static FILTER_KEYS: &[&str] = &["scope", "realm", "license", "author", "category"];
+
 pub struct SearchBackend {
     schema: Schema,
+    index: Index,
     writer: IndexWriter,
     reader: IndexReader,
     query_parser: QueryParser,
+
+    /// The package index commit that the search index was last brought up to
+    /// date with. This is stored alongside persisted search indexes so that we
+    /// can tell whether they need to be rebuilt.
+    indexed_commit: Option<String>,
 }
 
 impl SearchBackend {
-    pub fn new(package_index: &PackageIndex) -> anyhow::Result<Self> {
+    /// Create a search backend for the given package index. If a path is given,
+    /// the search index is kept on disk there and only rebuilt on startup if
+    /// the package index has changed since it was written.
+    pub fn new(package_index: &PackageIndex, path: Option<&Path>) -> anyhow::Result<Self> {
         let mut schema_builder = Schema::builder();
 
         let text_options = TextOptions::default()
@@ -32,13 +54,28 @@ impl SearchBackend {
             )
             .set_stored();
 
+        schema_builder.add_text_field("package", STRING | STORED);
         schema_builder.add_text_field("scope", text_options.clone());
+        schema_builder.add_text_field("scope_exact", STRING);
         schema_builder.add_text_field("name", text_options.clone());
         schema_builder.add_text_field("versions", TEXT | STORED);
-        schema_builder.add_text_field("description", text_options);
+        schema_builder.add_text_field("description", text_options.clone());
+        schema_builder.add_text_field("keywords", text_options);
+        schema_builder.add_text_field("categories", STRING | STORED);
+        schema_builder.add_text_field("documentation", STORED);
+        schema_builder.add_text_field("deprecated", STORED);
+        schema_builder.add_text_field("replacement", STORED);
+        schema_builder.add_text_field("authors", TEXT | STORED);
+        schema_builder.add_text_field("realm", STRING | STORED);
+        schema_builder.add_text_field("license", TEXT | STORED);
+        schema_builder.add_i64_field("updated", STORED);
+        schema_builder.add_text_field("depends_on", STRING);
 
         let schema = schema_builder.build();
-        let index = Index::create_in_ram(schema.clone());
+        let index = match path {
+            Some(path) => open_or_create_index(path, &schema)?,
+            None => Index::create_in_ram(schema.clone()),
+        };
 
         let analyzer = TextAnalyzer::from(NgramTokenizer::all_ngrams(2, 10)).filter(LowerCaser);
         index.tokenizers().register("ngram", analyzer);
@@ -53,32 +90,51 @@ impl SearchBackend {
         let scope = schema.get_field("scope").unwrap();
         let name = schema.get_field("name").unwrap();
         let description = schema.get_field("description").unwrap();
+        let keywords = schema.get_field("keywords").unwrap();
 
-        let mut query_parser = QueryParser::for_index(&index, vec![scope, name, description]);
+        let mut query_parser =
+            QueryParser::for_index(&index, vec![scope, name, description, keywords]);
         query_parser.set_conjunction_by_default();
         query_parser.set_field_boost(scope, 3.0);
         query_parser.set_field_boost(name, 5.0);
+        query_parser.set_field_boost(keywords, 2.0);
+
+        let indexed_commit = index.load_metas()?.payload;
 
         let mut backend = Self {
             schema,
+            index,
             writer,
             reader,
             query_parser,
+            indexed_commit,
         };
 
-        backend.crawl_packages(package_index)?;
+        if backend.indexed_commit() == Some(package_index.head_commit()?.as_str()) {
+            println!("Search index is already up to date");
+        } else {
+            backend.crawl_packages(package_index)?;
+        }
+
         Ok(backend)
     }
 
-    pub fn crawl_packages(&mut self, package_index: &PackageIndex) -> anyhow::Result<()> {
-        let scope = self.schema.get_field("scope").unwrap();
-        let name = self.schema.get_field("name").unwrap();
-        let versions = self.schema.get_field("versions").unwrap();
-        let description = self.schema.get_field("description").unwrap();
+    /// The package index commit that the search index is up to date with.
+    pub fn indexed_commit(&self) -> Option<&str> {
+        self.indexed_commit.as_deref()
+    }
 
+    /// How many packages are in the search index.
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    /// Rebuild the whole search index from scratch.
+    pub fn crawl_packages(&mut self, package_index: &PackageIndex) -> anyhow::Result<()> {
         println!("Crawling index...");
         let now = Instant::now();
-        self.writer.delete_all_documents()?;
+
+        let mut packages = Vec::new();
 
         for entry in WalkDir::new(package_index.path())
             .min_depth(1)
@@ -100,44 +156,286 @@ impl SearchBackend {
                 .to_str()
                 .unwrap();
             let package_name = path.file_name().unwrap().to_str().unwrap();
+            let package_name = PackageName::new(package_scope, package_name)?;
+            let relative_path = path.strip_prefix(package_index.path())?.to_owned();
 
-            let metadata = package_index
-                .get_package_metadata(&PackageName::new(package_scope, package_name)?)?;
+            packages.push((package_name, relative_path));
+        }
 
-            let mut doc = Document::default();
+        // Packages that haven't changed since we last indexed them keep the
+        // time we found back then, so we only have to look through history
+        // that we haven't seen yet.
+        let stored_times = self.stored_updated_times()?;
+        let package_paths: HashSet<&Path> =
+            packages.iter().map(|(_, path)| path.as_path()).collect();
+        let changed_times = last_updated_times(
+            package_index.path(),
+            self.indexed_commit.as_deref(),
+            &package_paths,
+        )?;
 
-            for manifest in &(*metadata).versions {
-                doc.add_text(versions, manifest.package.version.to_string());
+        self.writer.delete_all_documents()?;
 
-                if !manifest.package.version.is_prerelease() {
-                    doc.add_text(scope, manifest.package.name.scope());
-                    doc.add_text(name, manifest.package.name.name());
+        for (package_name, relative_path) in &packages {
+            let metadata = package_index.get_package_metadata(package_name)?;
+            let deprecations = package_index.get_deprecations(package_name)?;
+            let updated = changed_times
+                .get(relative_path)
+                .or_else(|| stored_times.get(&package_name.to_string()))
+                .copied();
+
+            self.writer.add_document(self.package_document(
+                package_name,
+                &metadata,
+                &deprecations,
+                updated,
+            ));
+        }
 
-                    if let Some(description_text) = &manifest.package.description {
-                        doc.add_text(description, description_text);
-                    }
+        self.commit(package_index)?;
+        println!("Finished crawling in {}ms", now.elapsed().as_millis());
 
-                    break;
-                }
+        Ok(())
+    }
+
+    /// When each package currently in the search index was last updated,
+    /// keyed by package name.
+    fn stored_updated_times(&self) -> anyhow::Result<HashMap<String, i64>> {
+        let searcher = self.reader.searcher();
+        let package = self.schema.get_field("package").unwrap();
+        let updated = self.schema.get_field("updated").unwrap();
+
+        let num_docs = searcher.num_docs() as usize;
+        let top_docs = searcher.search(&AllQuery, &TopDocs::with_limit(num_docs.max(1)))?;
+
+        let mut times = HashMap::new();
+
+        for (_score, doc_address) in top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let package_name = retrieved_doc.get_first(package).and_then(|v| v.text());
+
+            if let (Some(package_name), Some(Value::I64(time))) =
+                (package_name, retrieved_doc.get_first(updated))
+            {
+                times.insert(package_name.to_owned(), *time);
             }
+        }
 
-            self.writer.add_document(doc);
+        Ok(times)
+    }
+
+    /// When a single package in the search index was last updated, if it has
+    /// a document there.
+    fn stored_updated_time(&self, package_name: &PackageName) -> anyhow::Result<Option<i64>> {
+        self.reader.reload()?;
+        let searcher = self.reader.searcher();
+        let package = self.schema.get_field("package").unwrap();
+        let updated = self.schema.get_field("updated").unwrap();
+
+        let term = Term::from_field_text(package, &package_name.to_string());
+        let query = TermQuery::new(term, IndexRecordOption::Basic);
+
+        match searcher.search(&query, &TopDocs::with_limit(1))?.first() {
+            Some((_score, doc_address)) => match searcher.doc(*doc_address)?.get_first(updated) {
+                Some(Value::I64(time)) => Ok(Some(*time)),
+                _ => Ok(None),
+            },
+            None => Ok(None),
         }
+    }
 
-        self.writer.commit()?;
-        println!("Finished crawling in {}ms", now.elapsed().as_millis());
+    /// Add or replace the search document for a single package, such as after
+    /// a new version of it has been published or it has been deprecated.
+    /// Without an `updated` time, the package keeps the one it already had, or
+    /// the time its index entry last changed.
+    pub fn index_package(
+        &mut self,
+        package_index: &PackageIndex,
+        package_name: &PackageName,
+        updated: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let package = self.schema.get_field("package").unwrap();
+
+        let metadata = package_index.get_package_metadata(package_name)?;
+        let deprecations = package_index.get_deprecations(package_name)?;
+
+        let updated = match updated {
+            Some(updated) => Some(updated),
+            None => match self.stored_updated_time(package_name)? {
+                Some(updated) => Some(updated),
+                None => {
+                    let relative_path = Path::new(package_name.scope()).join(package_name.name());
+                    let package_paths = HashSet::from([relative_path.as_path()]);
+                    last_updated_times(package_index.path(), None, &package_paths)?
+                        .remove(&relative_path)
+                }
+            },
+        };
+
+        self.writer
+            .delete_term(Term::from_field_text(package, &package_name.to_string()));
+        self.writer.add_document(self.package_document(
+            package_name,
+            &metadata,
+            &deprecations,
+            updated,
+        ));
+        self.commit(package_index)?;
 
         Ok(())
     }
 
-    pub fn search(&self, query_input: &str) -> tantivy::Result<Vec<DocResult>> {
+    fn package_document(
+        &self,
+        package_name: &PackageName,
+        metadata: &PackageMetadata,
+        deprecations: &[Deprecation],
+        updated: Option<i64>,
+    ) -> Document {
+        let field = |name| self.schema.get_field(name).unwrap();
+
+        let mut doc = Document::default();
+        doc.add_text(field("package"), package_name.to_string());
+
+        if let Some(updated) = updated {
+            doc.add_i64(field("updated"), updated);
+        }
+
+        // Every package that any version of this one depends on, so that we
+        // can find dependents without reading the whole index.
+        let depends_on: BTreeSet<String> = metadata
+            .versions
+            .iter()
+            .flat_map(|manifest| {
+                manifest
+                    .dependencies
+                    .values()
+                    .chain(manifest.server_dependencies.values())
+            })
+            .map(|dependency| dependency.req().name().to_string())
+            .collect();
+
+        for dependency_name in depends_on {
+            doc.add_text(field("depends_on"), dependency_name);
+        }
+
+        for manifest in &metadata.versions {
+            doc.add_text(field("versions"), manifest.package.version.to_string());
+
+            if !manifest.package.version.is_prerelease() {
+                let package = &manifest.package;
+
+                doc.add_text(field("scope"), package.name.scope());
+                doc.add_text(field("scope_exact"), package.name.scope());
+                doc.add_text(field("name"), package.name.name());
+                doc.add_text(field("realm"), realm_name(package.realm));
+
+                if let Some(description_text) = &package.description {
+                    doc.add_text(field("description"), description_text);
+                }
+
+                if let Some(license) = &package.license {
+                    doc.add_text(field("license"), license);
+                }
+
+                for keyword in &package.keywords {
+                    doc.add_text(field("keywords"), keyword);
+                }
+
+                for category in &package.categories {
+                    doc.add_text(field("categories"), category);
+                }
+
+                if let Some(documentation) = &package.documentation {
+                    doc.add_text(field("documentation"), documentation);
+                }
+
+                for author in &package.authors {
+                    doc.add_text(field("authors"), author);
+                }
+
+                let deprecation = deprecations
+                    .iter()
+                    .find(|deprecation| deprecation.applies_to(&package.version));
+
+                if let Some(deprecation) = deprecation {
+                    doc.add_text(field("deprecated"), &deprecation.message);
+
+                    if let Some(replacement) = &deprecation.replacement {
+                        doc.add_text(field("replacement"), replacement.to_string());
+                    }
+                }
+
+                break;
+            }
+        }
+
+        doc
+    }
+
+    /// Commit pending changes to the search index, remembering which package
+    /// index commit they correspond to.
+    fn commit(&mut self, package_index: &PackageIndex) -> anyhow::Result<()> {
+        let head = package_index.head_commit()?;
+
+        let mut prepared_commit = self.writer.prepare_commit()?;
+        prepared_commit.set_payload(&head);
+        prepared_commit.commit()?;
+
+        self.indexed_commit = Some(head);
+
+        Ok(())
+    }
+
+    /// Search for packages. Words in the query of the form `key:value`, where
+    /// `key` is one of `FILTER_KEYS`, restrict results instead of being
+    /// matched against package names and descriptions.
+    pub fn search(
+        &self,
+        query_input: &str,
+        options: &SearchOptions,
+        stats: &DownloadStats,
+    ) -> tantivy::Result<SearchResults> {
         let searcher = self.reader.searcher();
-        let query = self
-            .query_parser
-            .parse_query(&query_input.replace("/", " "))?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(DOC_LIMIT))?;
 
-        let mut docs = Vec::with_capacity(DOC_LIMIT);
+        let mut text = Vec::new();
+        let mut subqueries = Vec::new();
+
+        for word in query_input.split_whitespace() {
+            match word.split_once(':') {
+                Some((key, value)) if FILTER_KEYS.contains(&key) && !value.is_empty() => {
+                    subqueries.push((Occur::Must, self.filter_query(key, value)?));
+                }
+                _ => text.push(word),
+            }
+        }
+
+        if !text.is_empty() {
+            let text_query = self
+                .query_parser
+                .parse_query(&text.join(" ").replace("/", " "))?;
+            subqueries.push((Occur::Must, text_query));
+        }
+
+        let query: Box<dyn Query> = if subqueries.is_empty() {
+            Box::new(AllQuery)
+        } else {
+            Box::new(BooleanQuery::from(subqueries))
+        };
+
+        let limit = options.limit.min(DOC_LIMIT);
+        let num_docs = searcher.num_docs() as usize;
+
+        // When sorting by anything other than relevance we need every match,
+        // otherwise packages that score lower could be cut off.
+        let collect_limit = match options.sort {
+            SearchSort::Relevance => options.offset.saturating_add(limit).min(num_docs),
+            _ => num_docs,
+        };
+        let (top_docs, total) =
+            searcher.search(&*query, &(TopDocs::with_limit(collect_limit.max(1)), Count))?;
+
+        let mut docs = Vec::with_capacity(top_docs.len());
 
         for (_score, doc_address) in top_docs {
             let retrieved_doc = searcher.doc(doc_address)?;
@@ -146,15 +444,286 @@ impl SearchBackend {
                 Ok(doc) => doc,
                 Err(_) => continue,
             };
+            let scope = retrieved_doc.scope[0].clone();
+            let name = retrieved_doc.name[0].clone();
+            let downloads = PackageName::new(&scope, &name)
+                .map(|package_name| stats.total_downloads(&package_name))
+                .unwrap_or(0);
+
             docs.push(DocResult {
-                scope: retrieved_doc.scope[0].clone(),
-                name: retrieved_doc.name[0].clone(),
+                scope,
+                name,
                 versions: retrieved_doc.versions,
                 description: retrieved_doc.description.map(|d| d[0].clone()),
+                downloads,
+                realm: retrieved_doc.realm.map(|r| r[0].clone()),
+                license: retrieved_doc.license.map(|l| l[0].clone()),
+                keywords: retrieved_doc.keywords,
+                categories: retrieved_doc.categories,
+                documentation: retrieved_doc.documentation.map(|d| d[0].clone()),
+                deprecated: retrieved_doc.deprecated.map(|d| d[0].clone()),
+                replacement: retrieved_doc.replacement.map(|r| r[0].clone()),
+                authors: retrieved_doc.authors,
+                updated: retrieved_doc.updated.map(|u| u[0]),
             });
         }
 
-        Ok(docs)
+        // Sorting is stable, so equal packages keep their relevance ordering.
+        match options.sort {
+            SearchSort::Relevance => {}
+            SearchSort::Downloads => docs.sort_by(|a, b| b.downloads.cmp(&a.downloads)),
+            SearchSort::Updated => docs.sort_by(|a, b| b.updated.cmp(&a.updated)),
+            SearchSort::Name => docs.sort_by(|a, b| (&a.name, &a.scope).cmp(&(&b.name, &b.scope))),
+        }
+
+        let docs = docs.into_iter().skip(options.offset).take(limit).collect();
+
+        Ok(SearchResults { total, docs })
+    }
+
+    /// Find every package version in the index with a shared or server
+    /// dependency on the given package.
+    pub fn dependents(
+        &self,
+        package_index: &PackageIndex,
+        package_name: &PackageName,
+    ) -> anyhow::Result<Vec<Dependent>> {
+        let searcher = self.reader.searcher();
+        let package = self.schema.get_field("package").unwrap();
+        let depends_on = self.schema.get_field("depends_on").unwrap();
+
+        let term = Term::from_field_text(depends_on, &package_name.to_string());
+        let query = TermQuery::new(term, IndexRecordOption::Basic);
+        let num_docs = searcher.num_docs() as usize;
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(num_docs.max(1)))?;
+
+        let mut dependents = Vec::new();
+
+        for (_score, doc_address) in top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let dependent_name = match retrieved_doc.get_first(package).and_then(|v| v.text()) {
+                Some(dependent_name) => dependent_name.parse::<PackageName>()?,
+                None => continue,
+            };
+
+            // The search index only tells us which packages to look at; the
+            // requirements themselves come from the package index.
+            let metadata = package_index.get_package_metadata(&dependent_name)?;
+
+            for manifest in &metadata.versions {
+                let realms = [
+                    (Realm::Shared, &manifest.dependencies),
+                    (Realm::Server, &manifest.server_dependencies),
+                ];
+
+                for &(realm, dependencies) in &realms {
+                    for (alias, dependency) in dependencies {
+                        let requirement = dependency.req();
+
+                        if requirement.name() == package_name {
+                            dependents.push(Dependent {
+                                package: manifest.package_id(),
+                                alias: alias.clone(),
+                                requirement: requirement.clone(),
+                                realm,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        dependents.sort_by(|a, b| (&a.package, &a.alias).cmp(&(&b.package, &b.alias)));
+
+        Ok(dependents)
+    }
+
+    fn filter_query(&self, key: &str, value: &str) -> tantivy::Result<Box<dyn Query>> {
+        let exact = |field_name| -> Box<dyn Query> {
+            let field = self.schema.get_field(field_name).unwrap();
+            let term = Term::from_field_text(field, &value.to_lowercase());
+            Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+        };
+
+        // Licenses and authors are matched as phrases so that `author:biff`
+        // finds "Biff Lumfer <biff@playadopt.me>".
+        let phrase = |field_name| -> tantivy::Result<Box<dyn Query>> {
+            let field = self.schema.get_field(field_name).unwrap();
+            let parser = QueryParser::for_index(&self.index, vec![field]);
+            Ok(parser.parse_query(&format!("\"{}\"", value.replace('"', "")))?)
+        };
+
+        match key {
+            "scope" => Ok(exact("scope_exact")),
+            "realm" => Ok(exact("realm")),
+            "license" => phrase("license"),
+            "author" => phrase("authors"),
+            "category" => Ok(exact("categories")),
+            _ => unreachable!("unknown search filter {}", key),
+        }
+    }
+}
+
+/// Options controlling which page of search results is returned and in which
+/// order.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub sort: SearchSort,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            sort: SearchSort::default(),
+            offset: 0,
+            limit: DOC_LIMIT,
+        }
+    }
+}
+
+/// How search results should be ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSort {
+    /// Best matches for the query first.
+    Relevance,
+
+    /// Most downloaded packages first.
+    Downloads,
+
+    /// Most recently published packages first.
+    Updated,
+
+    /// Alphabetical by package name.
+    Name,
+}
+
+impl Default for SearchSort {
+    fn default() -> Self {
+        SearchSort::Relevance
+    }
+}
+
+impl FromStr for SearchSort {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "relevance" => Ok(SearchSort::Relevance),
+            "downloads" => Ok(SearchSort::Downloads),
+            "updated" => Ok(SearchSort::Updated),
+            "name" => Ok(SearchSort::Name),
+            _ => bail!(
+                "unknown sort order '{}' (expected 'relevance', 'downloads', 'updated' or \
+                 'name')",
+                value
+            ),
+        }
+    }
+}
+
+/// One page of search results, along with how many packages matched in total.
+pub struct SearchResults {
+    pub total: usize,
+    pub docs: Vec<DocResult>,
+}
+
+/// A package version that depends on another package.
+#[derive(Debug, Clone, Serialize)]
+pub struct Dependent {
+    /// The package version that has the dependency.
+    pub package: PackageId,
+
+    /// The name the dependency is given in the dependent's manifest.
+    pub alias: String,
+
+    /// The range of versions that the dependent accepts.
+    pub requirement: PackageReq,
+
+    /// Whether this is a shared or server dependency.
+    pub realm: Realm,
+}
+
+/// Open a search index persisted at the given path, creating a new one if it
+/// doesn't exist yet or was written with a different schema.
+fn open_or_create_index(path: &Path, schema: &Schema) -> anyhow::Result<Index> {
+    if path.exists() {
+        let index = Index::open_in_dir(path)?;
+
+        if &index.schema() == schema {
+            return Ok(index);
+        }
+
+        println!("Search index schema has changed, rebuilding...");
+        fs_err::remove_dir_all(path)?;
+    }
+
+    fs_err::create_dir_all(path)?;
+    Ok(Index::create_in_dir(path, schema.clone())?)
+}
+
+/// Find when each of the given files in the package index was last changed in
+/// a commit after `since`, keyed by its path relative to the root of the index.
+/// Files that haven't changed since then are left out.
+fn last_updated_times(
+    index_path: &Path,
+    since: Option<&str>,
+    paths: &HashSet<&Path>,
+) -> anyhow::Result<HashMap<PathBuf, i64>> {
+    let repository = git2::Repository::open(index_path)?;
+
+    let mut revwalk = repository.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    revwalk.push_head()?;
+
+    // If the commit we indexed before has gone away, like after a force push,
+    // we have to look through everything.
+    let since = since
+        .and_then(|since| git2::Oid::from_str(since).ok())
+        .filter(|since| repository.find_commit(*since).is_ok());
+
+    if let Some(since) = since {
+        revwalk.hide(since)?;
+    }
+
+    let mut times = HashMap::new();
+
+    // Commits are visited newest first, so the first time we see a file is
+    // the last time it was changed.
+    for oid in revwalk {
+        if times.len() == paths.len() {
+            break;
+        }
+
+        let commit = repository.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+
+        let diff = repository.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path() {
+                if paths.contains(path) {
+                    times
+                        .entry(path.to_owned())
+                        .or_insert_with(|| commit.time().seconds());
+                }
+            }
+        }
+    }
+
+    Ok(times)
+}
+
+fn realm_name(realm: Realm) -> &'static str {
+    match realm {
+        Realm::Shared => "shared",
+        Realm::Server => "server",
+        Realm::Dev => "dev",
     }
 }
 
@@ -172,6 +741,18 @@ struct NativeDocResult {
     name: Vec<String>,
     versions: Vec<String>,
     description: Option<Vec<String>>,
+    realm: Option<Vec<String>>,
+    license: Option<Vec<String>>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    documentation: Option<Vec<String>>,
+    deprecated: Option<Vec<String>>,
+    replacement: Option<Vec<String>>,
+    #[serde(default)]
+    authors: Vec<String>,
+    updated: Option<Vec<i64>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -180,4 +761,14 @@ pub struct DocResult {
     name: String,
     versions: Vec<String>,
     description: Option<String>,
+    downloads: u64,
+    realm: Option<String>,
+    license: Option<String>,
+    keywords: Vec<String>,
+    categories: Vec<String>,
+    documentation: Option<String>,
+    deprecated: Option<String>,
+    replacement: Option<String>,
+    authors: Vec<String>,
+    updated: Option<i64>,
 }
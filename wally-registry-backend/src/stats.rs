@@ -0,0 +1,174 @@
+//! Keeps track of how often each package is downloaded from the registry.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use libwally::{package_id::PackageId, package_name::PackageName};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// How often download statistics are written to disk. Downloads counted since
+/// the last write are lost if the registry crashes, but not if it shuts down
+/// normally.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Download counts for every version of a single package.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PackageStats {
+    pub total: u64,
+    pub versions: BTreeMap<Version, VersionStats>,
+}
+
+/// Download counts for a single version of a package, bucketed by UTC day in
+/// the form `YYYY-MM-DD`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VersionStats {
+    pub total: u64,
+    pub daily: BTreeMap<String, u64>,
+}
+
+pub struct DownloadStats {
+    /// Where the statistics are persisted. If this is `None`, statistics are
+    /// only kept in memory and are lost when the registry restarts.
+    path: Option<PathBuf>,
+
+    shared: Arc<Shared>,
+}
+
+/// The parts of [`DownloadStats`] that the background flush thread can see.
+#[derive(Default)]
+struct Shared {
+    packages: Mutex<BTreeMap<PackageName, PackageStats>>,
+
+    /// Whether any downloads have been counted since the last flush.
+    dirty: AtomicBool,
+
+    /// Held while writing the statistics file, so that a flush from the
+    /// background thread can't overlap the final flush on shutdown.
+    write_lock: Mutex<()>,
+}
+
+impl DownloadStats {
+    /// Load download statistics from the given file, starting from scratch if
+    /// the file does not exist yet. Statistics are written back to the file
+    /// periodically and when this is dropped.
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let packages = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs_err::read_to_string(path)?;
+                serde_json::from_str(&contents).with_context(|| {
+                    format!("could not parse download statistics at {}", path.display())
+                })?
+            }
+            _ => BTreeMap::new(),
+        };
+
+        let shared = Arc::new(Shared {
+            packages: Mutex::new(packages),
+            ..Default::default()
+        });
+
+        if let Some(path) = &path {
+            let path = path.clone();
+            let shared = Arc::downgrade(&shared);
+
+            thread::spawn(move || flush_periodically(&path, shared));
+        }
+
+        Ok(Self { path, shared })
+    }
+
+    /// Count a single download of the given package for today's date. This
+    /// only updates the counts in memory; they're written to disk later.
+    pub fn record_download(&self, package_id: &PackageId) {
+        let today = OffsetDateTime::now_utc().date().to_string();
+        let mut packages = self.shared.packages.lock().unwrap();
+
+        let package = packages.entry(package_id.name().clone()).or_default();
+        package.total += 1;
+
+        let version = package
+            .versions
+            .entry(package_id.version().clone())
+            .or_default();
+        version.total += 1;
+        *version.daily.entry(today).or_default() += 1;
+
+        self.shared.dirty.store(true, Ordering::SeqCst);
+    }
+
+    pub fn package_stats(&self, name: &PackageName) -> PackageStats {
+        let packages = self.shared.packages.lock().unwrap();
+        packages.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn total_downloads(&self, name: &PackageName) -> u64 {
+        let packages = self.shared.packages.lock().unwrap();
+        packages.get(name).map(|stats| stats.total).unwrap_or(0)
+    }
+}
+
+impl Drop for DownloadStats {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if let Err(err) = self.shared.flush(path) {
+                println!("Could not save download statistics: {:?}", err);
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Write the statistics to disk if anything has changed since the last
+    /// flush. The file is written without holding the lock on the counts, so
+    /// downloads aren't held up by disk I/O.
+    fn flush(&self, path: &Path) -> anyhow::Result<()> {
+        let _write_guard = self.write_lock.lock().unwrap();
+
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let contents = {
+            let packages = self.packages.lock().unwrap();
+            serde_json::to_string(&*packages)?
+        };
+
+        // Write to a sibling file first so that a crash mid-write can't
+        // leave us with a truncated statistics file.
+        let temp_path = path.with_extension("tmp");
+        let result = fs_err::write(&temp_path, contents)
+            .and_then(|_| fs_err::rename(&temp_path, path))
+            .context("could not write download statistics");
+
+        if result.is_err() {
+            // Try again next time around.
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+
+        result
+    }
+}
+
+/// Flush statistics every [`FLUSH_INTERVAL`] until the [`DownloadStats`] they
+/// belong to is dropped.
+fn flush_periodically(path: &Path, shared: Weak<Shared>) {
+    loop {
+        thread::sleep(FLUSH_INTERVAL);
+
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => break,
+        };
+
+        if let Err(err) = shared.flush(path) {
+            println!("Could not save download statistics: {:?}", err);
+        }
+    }
+}
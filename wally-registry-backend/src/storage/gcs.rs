@@ -1,17 +1,25 @@
 use std::convert::Infallible;
 use std::io::Cursor;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use async_trait::async_trait;
-use cloud_storage_lite::client::{BucketClient, GcsBucketClient};
+use cloud_storage_lite::{
+    client::{BucketClient, GcsBucketClient, ListObjectOptions},
+    Error as GcsError,
+};
 use futures::TryStreamExt;
 use libwally::package_id::PackageId;
 use moka::sync::Cache;
+use time::OffsetDateTime;
 
-use super::{StorageBackend, StorageOutput};
+use super::{file_chunks, CacheStats, StorageBackend, StorageMetadata, StorageOutput};
 
 pub struct GcsStorage {
     client: GcsBucketClient,
     cache: Option<Cache<PackageId, Vec<u8>>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
 }
 
 impl GcsStorage {
@@ -23,6 +31,8 @@ impl GcsStorage {
         Self {
             client,
             cache: cache_size.map(Cache::new),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
         }
     }
 }
@@ -31,9 +41,12 @@ impl GcsStorage {
 impl StorageBackend for GcsStorage {
     async fn read(&self, key: &PackageId) -> anyhow::Result<StorageOutput> {
         if let Some(cache) = &self.cache {
-            if cache.contains_key(key) {
-                return Ok(Box::new(Cursor::new(cache.get(key).unwrap())));
+            if let Some(data) = cache.get(key) {
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Box::new(Cursor::new(data)));
             }
+
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
         }
 
         let name = key.to_string();
@@ -59,4 +72,94 @@ impl StorageBackend for GcsStorage {
 
         Ok(())
     }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        self.client.delete_object(&id.to_string()).await?;
+
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        match self.client.get_object(&id.to_string()).await {
+            Ok(_) => Ok(true),
+            Err(GcsError::NotFound) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self, scope: Option<&str>) -> anyhow::Result<Vec<PackageId>> {
+        let prefix = scope.map(|scope| format!("{}/", scope));
+        let objects: Vec<_> = self
+            .client
+            .list_objects(ListObjectOptions {
+                prefix: prefix.as_deref(),
+                ..Default::default()
+            })
+            .await?
+            .try_collect()
+            .await?;
+
+        // Objects are named after the package ID they contain, so anything
+        // else in the bucket isn't a package.
+        let mut package_ids: Vec<PackageId> = objects
+            .into_iter()
+            .filter_map(|object| object.name.parse().ok())
+            .collect();
+
+        package_ids.sort();
+        Ok(package_ids)
+    }
+
+    async fn metadata(&self, id: &PackageId) -> anyhow::Result<StorageMetadata> {
+        let object = self.client.get_object(&id.to_string()).await?;
+
+        Ok(StorageMetadata {
+            size: object.size,
+            last_modified: OffsetDateTime::from_unix_timestamp(object.updated.timestamp()).ok(),
+        })
+    }
+
+    async fn stage(&self, id: &PackageId, contents: &Path) -> anyhow::Result<()> {
+        self.client
+            .create_object(&staged_name(id), file_chunks(contents).await?)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn promote(&self, id: &PackageId) -> anyhow::Result<()> {
+        // There's no way to rename an object, so copy it through us instead.
+        let staged_name = staged_name(id);
+        let stream = self.client.download_object(&staged_name).await?;
+        let data = stream.map_ok(|chunk| chunk.to_vec()).try_concat().await?;
+
+        self.write(id, &data).await?;
+        self.client.delete_object(&staged_name).await?;
+
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|_| CacheStats {
+            hits: self.cache_hits.load(Ordering::Relaxed),
+            misses: self.cache_misses.load(Ordering::Relaxed),
+        })
+    }
+
+    async fn discard(&self, id: &PackageId) -> anyhow::Result<()> {
+        match self.client.delete_object(&staged_name(id)).await {
+            Ok(_) | Err(GcsError::NotFound) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Staged packages are kept under a prefix that can't be a package scope, so
+/// that they're never served or listed.
+fn staged_name(id: &PackageId) -> String {
+    format!(".staging/{}", id)
 }
@@ -1,12 +1,15 @@
+use std::io;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use async_trait::async_trait;
-use libwally::package_id::PackageId;
-use tokio::fs::{create_dir_all, File, OpenOptions};
+use libwally::{package_id::PackageId, package_name::PackageName};
+use semver::Version;
+use time::OffsetDateTime;
+use tokio::fs::{self, create_dir_all, File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 
-use super::{StorageBackend, StorageOutput};
+use super::{StorageBackend, StorageMetadata, StorageOutput};
 
 pub struct LocalStorage {
     path: Option<PathBuf>,
@@ -47,6 +50,155 @@ impl StorageBackend for LocalStorage {
         file.write_all(contents).await?;
         Ok(())
     }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        let path = package_path(self.path.as_deref(), id)?;
+        fs::remove_file(&path)
+            .await
+            .with_context(|| format!("could not remove {}", path.display()))?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        let path = package_path(self.path.as_deref(), id)?;
+
+        match fs::metadata(&path).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).with_context(|| format!("could not read {}", path.display())),
+        }
+    }
+
+    async fn list(&self, scope: Option<&str>) -> anyhow::Result<Vec<PackageId>> {
+        let base_path = self
+            .path
+            .as_deref()
+            .unwrap_or_else(|| Path::new("packages"));
+
+        let scopes = match scope {
+            Some(scope) => {
+                anyhow::ensure!(
+                    !scope.contains(|c| c == '/' || c == '\\' || c == '.'),
+                    "Cannot escape packages directory"
+                );
+
+                vec![scope.to_owned()]
+            }
+            None => dir_names(base_path).await?,
+        };
+
+        let mut package_ids = Vec::new();
+
+        for scope in scopes {
+            let scope_path = base_path.join(&scope);
+
+            for name in dir_names(&scope_path).await? {
+                let package_path = scope_path.join(&name);
+                let mut entries = fs::read_dir(&package_path).await?;
+
+                while let Some(entry) = entries.next_entry().await? {
+                    let file_name = entry.file_name();
+                    let version = match file_name.to_str().and_then(|s| s.strip_suffix(".zip")) {
+                        Some(version) => version,
+                        None => continue,
+                    };
+
+                    // Anything that doesn't look like a package archive wasn't
+                    // written by us, so leave it out.
+                    if let (Ok(name), Ok(version)) =
+                        (PackageName::new(&scope, &name), version.parse::<Version>())
+                    {
+                        package_ids.push(PackageId::new(name, version));
+                    }
+                }
+            }
+        }
+
+        package_ids.sort();
+        Ok(package_ids)
+    }
+
+    async fn metadata(&self, id: &PackageId) -> anyhow::Result<StorageMetadata> {
+        let path = package_path(self.path.as_deref(), id)?;
+        let metadata = fs::metadata(&path)
+            .await
+            .with_context(|| format!("could not read {}", path.display()))?;
+
+        Ok(StorageMetadata {
+            size: metadata.len(),
+            last_modified: metadata.modified().ok().map(OffsetDateTime::from),
+        })
+    }
+
+    async fn stage(&self, id: &PackageId, contents: &Path) -> anyhow::Result<()> {
+        let path = staged_path(self.path.as_deref(), id)?;
+        let directory = path.parent().unwrap();
+
+        create_dir_all(&directory)
+            .await
+            .with_context(|| format!("could not create directory {}", directory.display()))?;
+
+        fs::copy(contents, &path)
+            .await
+            .with_context(|| format!("could not write {}", path.display()))?;
+
+        Ok(())
+    }
+
+    async fn promote(&self, id: &PackageId) -> anyhow::Result<()> {
+        let staged_path = staged_path(self.path.as_deref(), id)?;
+        let path = package_path(self.path.as_deref(), id)?;
+
+        fs::rename(&staged_path, &path).await.with_context(|| {
+            format!(
+                "could not move {} to {}",
+                staged_path.display(),
+                path.display()
+            )
+        })?;
+
+        Ok(())
+    }
+
+    async fn discard(&self, id: &PackageId) -> anyhow::Result<()> {
+        let path = staged_path(self.path.as_deref(), id)?;
+
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("could not remove {}", path.display())),
+        }
+    }
+}
+
+/// Staged packages sit next to where they'll end up, but without the `.zip`
+/// extension so that they're never served or listed.
+fn staged_path(package_directory: Option<&Path>, id: &PackageId) -> anyhow::Result<PathBuf> {
+    let path = package_path(package_directory, id)?;
+    Ok(path.with_extension("zip.staged"))
+}
+
+/// The names of all directories inside the given directory, which may not
+/// exist yet.
+async fn dir_names(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut entries = match fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("could not read {}", path.display())),
+    };
+
+    let mut names = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_owned());
+            }
+        }
+    }
+
+    Ok(names)
 }
 
 fn package_path(package_directory: Option<&Path>, id: &PackageId) -> anyhow::Result<PathBuf> {
@@ -4,12 +4,18 @@ mod local;
 #[cfg(feature = "s3-storage")]
 mod s3;
 
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
-use libwally::package_id::PackageId;
+use futures::Stream;
+use libwally::{package_id::PackageId, package_index::PackageIndex};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncRead;
+use time::OffsetDateTime;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt},
+};
 
 pub use gcs::GcsStorage;
 pub use local::LocalStorage;
@@ -40,8 +46,98 @@ pub enum StorageMode {
 
 pub type StorageOutput = Box<dyn AsyncRead + Unpin + Send + Sync + 'static>;
 
+/// Information about the stored contents of a package.
+#[derive(Debug, Clone)]
+pub struct StorageMetadata {
+    /// The size of the package archive in bytes.
+    pub size: u64,
+
+    /// When the package archive was last written, if the backend knows.
+    pub last_modified: Option<OffsetDateTime>,
+}
+
+/// How often reads were answered from a backend's in-memory cache.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
 #[async_trait]
 pub trait StorageBackend: Send + Sync + 'static {
     async fn read(&self, id: &PackageId) -> anyhow::Result<StorageOutput>;
     async fn write(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()>;
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()>;
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool>;
+
+    /// List every package with stored contents, optionally only those in the
+    /// given scope.
+    async fn list(&self, scope: Option<&str>) -> anyhow::Result<Vec<PackageId>>;
+
+    async fn metadata(&self, id: &PackageId) -> anyhow::Result<StorageMetadata>;
+
+    /// Copy the package archive at the given path somewhere it won't be served
+    /// from until `promote` is called. Staging the same package again replaces
+    /// whatever was staged before, so that a failed publish can be retried.
+    async fn stage(&self, id: &PackageId, contents: &Path) -> anyhow::Result<()>;
+
+    /// Move staged package contents to where they will be served from.
+    async fn promote(&self, id: &PackageId) -> anyhow::Result<()>;
+
+    /// Throw away staged package contents that won't be promoted.
+    async fn discard(&self, id: &PackageId) -> anyhow::Result<()>;
+
+    /// Cache statistics, for backends that keep a cache.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+/// How much of a file to read at a time when uploading it.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Read a file as a stream of chunks, so that backends can upload it without
+/// loading all of it into memory.
+async fn file_chunks(
+    path: &Path,
+) -> anyhow::Result<impl Stream<Item = io::Result<Vec<u8>>> + Send + Sync + 'static> {
+    let file = File::open(path).await?;
+
+    Ok(futures::stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = file.read(&mut chunk).await?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        chunk.truncate(read);
+        Ok(Some((chunk, file)))
+    }))
+}
+
+/// Find packages that have stored contents but no entry in the package index,
+/// such as when publishing failed after the contents were written.
+pub async fn orphaned_packages(
+    storage: &dyn StorageBackend,
+    index: &PackageIndex,
+    scope: Option<&str>,
+) -> anyhow::Result<Vec<PackageId>> {
+    let mut orphans = Vec::new();
+
+    for package_id in storage.list(scope).await? {
+        let in_index = match index.get_package_metadata(package_id.name()) {
+            Ok(metadata) => metadata
+                .versions
+                .iter()
+                .any(|manifest| &manifest.package.version == package_id.version()),
+            Err(_) => false,
+        };
+
+        if !in_index {
+            orphans.push(package_id);
+        }
+    }
+
+    Ok(orphans)
 }
@@ -1,18 +1,38 @@
 use std::io::Cursor;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::TryStreamExt;
 use libwally::package_id::PackageId;
 use moka::sync::Cache;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 
-use rusoto_s3::{GetObjectRequest, PutObjectRequest, S3Client, S3};
+use rusoto_core::{ByteStream, RusotoError};
+use rusoto_s3::{
+    CopyObjectRequest, DeleteObjectRequest, GetObjectRequest, HeadObjectError, HeadObjectRequest,
+    ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
-use super::{StorageBackend, StorageOutput};
+use super::{file_chunks, CacheStats, StorageBackend, StorageMetadata, StorageOutput};
+
+/// Characters that are escaped in a `CopySource`, which is a URL path. Slashes
+/// separate the bucket from the key and are left alone.
+const COPY_SOURCE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
 
 pub struct S3Storage {
     client: S3Client,
     bucket: String,
     cache: Option<Cache<PackageId, Vec<u8>>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
 }
 
 impl S3Storage {
@@ -25,6 +45,8 @@ impl S3Storage {
             client,
             bucket,
             cache: cache_size.map(Cache::new),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
         }
     }
 }
@@ -33,9 +55,12 @@ impl S3Storage {
 impl StorageBackend for S3Storage {
     async fn read(&self, key: &PackageId) -> anyhow::Result<StorageOutput> {
         if let Some(cache) = &self.cache {
-            if cache.contains_key(key) {
-                return Ok(Box::new(Cursor::new(cache.get(key).unwrap())));
+            if let Some(data) = cache.get(key) {
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Box::new(Cursor::new(data)));
             }
+
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
         }
 
         let name = key.to_string();
@@ -74,4 +99,177 @@ impl StorageBackend for S3Storage {
 
         Ok(())
     }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: id.to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        let result = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: id.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        // HEAD responses have no body, so a missing object usually comes back
+        // as an unknown error rather than NoSuchKey.
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self, scope: Option<&str>) -> anyhow::Result<Vec<PackageId>> {
+        let mut package_ids = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let output = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_owned(),
+                    prefix: scope.map(|scope| format!("{}/", scope)),
+                    continuation_token,
+                    ..Default::default()
+                })
+                .await?;
+
+            // Objects are named after the package ID they contain, so anything
+            // else in the bucket isn't a package.
+            for object in output.contents.unwrap_or_default() {
+                if let Some(package_id) = object.key.and_then(|key| key.parse().ok()) {
+                    package_ids.push(package_id);
+                }
+            }
+
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated == Some(true) => {
+                    continuation_token = Some(token)
+                }
+                _ => break,
+            }
+        }
+
+        package_ids.sort();
+        Ok(package_ids)
+    }
+
+    async fn metadata(&self, id: &PackageId) -> anyhow::Result<StorageMetadata> {
+        let key = id.to_string();
+
+        // Listing gives us an RFC 3339 timestamp, unlike HEAD which uses the
+        // HTTP date format.
+        let output = self
+            .client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: self.bucket.to_owned(),
+                prefix: Some(key.clone()),
+                max_keys: Some(1),
+                ..Default::default()
+            })
+            .await?;
+
+        let object = output
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .find(|object| object.key.as_deref() == Some(key.as_str()))
+            .ok_or_else(|| anyhow::format_err!("{} is not in storage", id))?;
+
+        Ok(StorageMetadata {
+            size: object.size.unwrap_or(0) as u64,
+            last_modified: object
+                .last_modified
+                .and_then(|date| OffsetDateTime::parse(&date, &Rfc3339).ok()),
+        })
+    }
+
+    async fn stage(&self, id: &PackageId, contents: &Path) -> anyhow::Result<()> {
+        let size = tokio::fs::metadata(contents).await?.len();
+        let chunks = file_chunks(contents).await?.map_ok(Bytes::from);
+
+        self.client
+            .put_object(PutObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: staged_name(id),
+                body: Some(ByteStream::new_with_size(chunks, size as usize)),
+                content_length: Some(size as i64),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn promote(&self, id: &PackageId) -> anyhow::Result<()> {
+        let staged_name = staged_name(id);
+
+        self.client
+            .copy_object(CopyObjectRequest {
+                bucket: self.bucket.to_owned(),
+                // S3 decodes the source, so a `+` in a version's build
+                // metadata would otherwise turn into a space.
+                copy_source: utf8_percent_encode(
+                    &format!("{}/{}", self.bucket, staged_name),
+                    COPY_SOURCE,
+                )
+                .to_string(),
+                key: id.to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: staged_name,
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|_| CacheStats {
+            hits: self.cache_hits.load(Ordering::Relaxed),
+            misses: self.cache_misses.load(Ordering::Relaxed),
+        })
+    }
+
+    async fn discard(&self, id: &PackageId) -> anyhow::Result<()> {
+        // Deleting an object that doesn't exist succeeds in S3.
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: staged_name(id),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Staged packages are kept under a prefix that can't be a package scope, so
+/// that they're never served or listed.
+fn staged_name(id: &PackageId) -> String {
+    format!(".staging/{}", id)
 }
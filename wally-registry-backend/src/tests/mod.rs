@@ -1,13 +1,35 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use figment::{providers::Serialized, Figment};
-use libwally::test_package::PackageBuilder;
+use libwally::{
+    manifest::Realm,
+    package_index::{Deprecation, PackageIndex},
+    test_package::PackageBuilder,
+};
 use rocket::{
     http::{Accept, ContentType, Header, Status},
-    local::blocking::{Client, LocalResponse},
+    local::{
+        asynchronous,
+        blocking::{Client, LocalResponse},
+    },
 };
 
-use crate::{auth::AuthMode, config::Config, server, storage::StorageMode};
+use crate::{
+    auth::AuthMode,
+    config::Config,
+    cors::CorsConfig,
+    mirror::MirrorConfig,
+    rate_limit::{RateLimit, RateLimitConfig},
+    server,
+    storage::StorageMode,
+    MIN_TABLE_DEPENDENCY_VERSION,
+};
 
 fn init_test_index_remote() -> anyhow::Result<url::Url> {
     let temp_dir = tempfile::tempdir()?;
@@ -49,10 +71,20 @@ fn new_client(auth: AuthMode) -> Client {
 }
 
 fn new_client_with_remote(auth: AuthMode, index_url: url::Url) -> Client {
+    new_client_with_config(test_config(auth, index_url))
+}
+
+fn new_client_with_config(config: Config) -> Client {
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(config));
+
+    Client::tracked(server(figment)).expect("valid rocket instance")
+}
+
+fn test_config(auth: AuthMode, index_url: url::Url) -> Config {
     let package_path = tempfile::tempdir().unwrap().into_path();
     add_test_packages(&package_path).unwrap();
 
-    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(Config {
+    Config {
         index_url,
         storage: StorageMode::Local {
             path: Some(package_path),
@@ -60,9 +92,14 @@ fn new_client_with_remote(auth: AuthMode, index_url: url::Url) -> Client {
         auth,
         github_token: None,
         minimum_wally_version: None,
-    }));
-
-    Client::tracked(server(figment)).expect("valid rocket instance")
+        stats_path: None,
+        search_index_path: None,
+        mirror: None,
+        max_package_size: None,
+        audit_log_path: None,
+        rate_limit: RateLimitConfig::default(),
+        cors: CorsConfig::default(),
+    }
 }
 
 struct Expectation {
@@ -250,6 +287,43 @@ fn publish_duplicate() {
     .assert(send_request());
 }
 
+#[test]
+fn publish_table_dependencies_needs_new_client() {
+    let contents = PackageBuilder::new("biff/hello@0.1.0")
+        .with_detailed_dep("Util", "biff/util@1.0.0", true, vec!["extra"])
+        .contents();
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+    let send_request = |version: &str| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(Header::new("Wally-Version", version.to_owned()))
+            .dispatch()
+    };
+
+    Expectation {
+        status: Status::UpgradeRequired,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request("0.1.0"));
+
+    // The last release before table-form dependencies, which is also the
+    // version this registry is built as, still can't publish them.
+    Expectation {
+        status: Status::UpgradeRequired,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request("0.3.2"));
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request(MIN_TABLE_DEPENDENCY_VERSION));
+}
+
 #[test]
 fn publish_updates_git_remote() {
     let remote = init_test_index_remote().unwrap();
@@ -308,7 +382,1126 @@ fn publish_then_publish_elsewhere() {
     .assert(response);
 }
 
+#[test]
+fn download_stats() {
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    for _ in 0..3 {
+        let response = client
+            .get("/v1/package-contents/biff/hello/1.0.0")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::GZIP,
+        }
+        .assert(response);
+    }
+
+    let response = client
+        .get("/v1/package-stats/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let stats: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(stats["total"], 3);
+    assert_eq!(stats["versions"]["1.0.0"]["total"], 3);
+
+    let response = client
+        .get("/v1/package-search?query=hello&sort=downloads")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let results: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(results[0]["downloads"], 3);
+}
+
+#[test]
+fn download_stats_unknown_package_404() {
+    let client = new_client(AuthMode::Unauthenticated);
+    let response = client.get("/v1/package-stats/biff/doesnt-exist").dispatch();
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn download_stats_persist() {
+    let remote = init_test_index_remote().unwrap();
+    let stats_path = tempfile::tempdir().unwrap().into_path().join("stats.json");
+
+    {
+        let mut config = test_config(AuthMode::ApiKey(String::from("hello")), remote.clone());
+        config.stats_path = Some(stats_path.clone());
+        let client = new_client_with_config(config);
+
+        let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get("/v1/package-contents/biff/hello/1.0.0")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    // Counts are written out when the registry shuts down, so a registry
+    // starting back up should see them.
+    let mut config = test_config(AuthMode::Unauthenticated, remote);
+    config.stats_path = Some(stats_path);
+    let client = new_client_with_config(config);
+
+    let response = client.get("/v1/package-stats/biff/hello").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let stats: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(stats["total"], 1);
+}
+
+#[test]
+fn search_index_persists() {
+    let remote = init_test_index_remote().unwrap();
+    let search_index_path = tempfile::tempdir().unwrap().into_path().join("search");
+
+    {
+        let mut config = test_config(AuthMode::ApiKey(String::from("hello")), remote.clone());
+        config.search_index_path = Some(search_index_path.clone());
+        let client = new_client_with_config(config);
+
+        let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+
+    // A registry starting back up should pick up the existing search index.
+    let mut config = test_config(AuthMode::Unauthenticated, remote);
+    config.search_index_path = Some(search_index_path);
+    let client = new_client_with_config(config);
+
+    let response = client.get("/v1/package-search?query=hello").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let results: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(results[0]["name"], "hello");
+}
+
+#[test]
+fn search_filters_and_pagination() {
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+
+    for package in [
+        PackageBuilder::new("biff/alpha@1.0.0"),
+        PackageBuilder::new("biff/beta@1.0.0").with_realm(Realm::Server),
+        PackageBuilder::new("other/gamma@1.0.0"),
+    ]
+    .iter()
+    {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+
+    let search = |query: &str| {
+        let uri = format!("/v1/package-search?{}", query);
+        let response = client
+            .get(uri.as_str())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let total = response
+            .headers()
+            .get_one("Wally-Total-Count")
+            .unwrap()
+            .to_owned();
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let names: Vec<String> = results
+            .iter()
+            .map(|result| result["name"].as_str().unwrap().to_owned())
+            .collect();
+
+        (total, names)
+    };
+
+    assert_eq!(
+        search("query=scope:biff&sort=name"),
+        (
+            String::from("2"),
+            vec![String::from("alpha"), String::from("beta")]
+        )
+    );
+    assert_eq!(
+        search("query=realm:server"),
+        (String::from("1"), vec![String::from("beta")])
+    );
+    assert_eq!(
+        search("query=scope:biff&sort=name&offset=1&limit=1"),
+        (String::from("2"), vec![String::from("beta")])
+    );
+}
+
+#[test]
+fn mirror_package_info() {
+    let upstream = init_test_index_remote().unwrap();
+    PackageIndex::new_temp(&upstream, None)
+        .unwrap()
+        .publish(PackageBuilder::new("biff/upstream@1.0.0").manifest())
+        .unwrap();
+
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.mirror = Some(MirrorConfig {
+        index_url: upstream,
+    });
+    let client = new_client_with_config(config);
+
+    let response = client.get("/v1/package-metadata/biff/upstream").dispatch();
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    // Packages that exist in neither registry are still missing.
+    let response = client
+        .get("/v1/package-metadata/biff/doesnt-exist")
+        .dispatch();
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
 // TODO: Implement yanking
 #[test]
 #[ignore]
 fn yank() {}
+
+#[test]
+fn package_dependents() {
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+
+    let packages = [
+        PackageBuilder::new("biff/lib@1.0.0"),
+        PackageBuilder::new("biff/app@1.0.0").with_dep("Lib", "biff/lib@1.0.0"),
+        PackageBuilder::new("biff/app@2.0.0").with_dep("Lib", "biff/lib@2.0.0"),
+        PackageBuilder::new("biff/game@1.0.0").with_server_dep("Library", "biff/lib@1.0.0"),
+        PackageBuilder::new("biff/unrelated@1.0.0"),
+    ];
+
+    for package in &packages {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+
+    let response = client
+        .get("/v1/package-dependents/biff/lib")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let dependents: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(
+        dependents,
+        serde_json::json!([
+            {
+                "package": "biff/app@1.0.0",
+                "alias": "Lib",
+                "requirement": "biff/lib@>=1.0.0, <2.0.0",
+                "realm": "shared",
+            },
+            {
+                "package": "biff/app@2.0.0",
+                "alias": "Lib",
+                "requirement": "biff/lib@>=2.0.0, <3.0.0",
+                "realm": "shared",
+            },
+            {
+                "package": "biff/game@1.0.0",
+                "alias": "Library",
+                "requirement": "biff/lib@>=1.0.0, <2.0.0",
+                "realm": "server",
+            },
+        ])
+    );
+
+    let response = client
+        .get("/v1/package-dependents/biff/unrelated")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().unwrap(), "[]");
+}
+
+#[test]
+fn package_readme_and_metadata() {
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+
+    let package = PackageBuilder::new("biff/documented@1.0.0")
+        .with_readme("docs/README.md", "# Documented\n")
+        .with_keywords(vec!["docs"])
+        .with_categories(vec!["user-interface"]);
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(package.contents().data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    let response = client
+        .get("/v1/package-readme/biff/documented/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().unwrap(), "# Documented\n");
+
+    let response = client
+        .get("/v1/package-search?query=category:user-interface")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let results: Vec<serde_json::Value> =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0]["categories"],
+        serde_json::json!(["user-interface"])
+    );
+
+    let too_many_keywords = PackageBuilder::new("biff/keywords@1.0.0")
+        .with_keywords(vec!["a", "b", "c", "d", "e", "f"]);
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(too_many_keywords.contents().data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn deprecate_package() {
+    let remote = init_test_index_remote().unwrap();
+    let client = new_client_with_remote(AuthMode::ApiKey(String::from("hello")), remote.clone());
+
+    let packages = [
+        PackageBuilder::new("biff/old-util@1.0.0"),
+        PackageBuilder::new("biff/old-util@1.1.0"),
+    ];
+
+    for package in &packages {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+
+    // Search times are only kept to the second, so wait long enough for each
+    // step to be told apart.
+    thread::sleep(Duration::from_secs(1));
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(PackageBuilder::new("biff/new-util@1.0.0").contents().data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let updated_order = || {
+        let response = client
+            .get("/v1/package-search?query=scope:biff&sort=updated")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        results
+            .iter()
+            .map(|result| result["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(updated_order(), vec!["new-util", "old-util"]);
+
+    let deprecate = |package: &str, body: serde_json::Value| {
+        client
+            .post(format!("/v1/package-deprecate/{}", package))
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .status()
+    };
+
+    let deprecation = serde_json::json!({
+        "message": "Merged into biff/new-util",
+        "replacement": "biff/new-util",
+    });
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(deprecate("biff/old-util", deprecation.clone()), Status::Ok);
+    assert_eq!(deprecate("biff/missing", deprecation), Status::NotFound);
+    assert_eq!(
+        deprecate(
+            "biff/old-util",
+            serde_json::json!({ "versions": ">=2.0.0", "message": "Too new" })
+        ),
+        Status::BadRequest
+    );
+    assert_eq!(
+        deprecate("biff/old-util", serde_json::json!({ "message": " " })),
+        Status::BadRequest
+    );
+
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    let deprecations = index
+        .get_deprecations(&"biff/old-util".parse().unwrap())
+        .unwrap();
+    assert_eq!(deprecations.len(), 1);
+    assert_eq!(deprecations[0].message, "Merged into biff/new-util");
+
+    let response = client
+        .get("/v1/package-search?query=scope:biff")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let results: Vec<serde_json::Value> =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    let old_util = results
+        .iter()
+        .find(|result| result["name"] == "old-util")
+        .unwrap();
+    assert_eq!(old_util["deprecated"], "Merged into biff/new-util");
+    assert_eq!(old_util["replacement"], "biff/new-util");
+
+    // Deprecating a package doesn't move it up the recently updated list.
+    assert_eq!(updated_order(), vec!["new-util", "old-util"]);
+}
+
+#[test]
+fn orphaned_packages() {
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    // The test packages are in storage but were never published to the index.
+    let response = client
+        .get("/v1/admin/orphaned-packages")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let orphans: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(orphans, serde_json::json!(["biff/minimal@0.1.0"]));
+
+    let response = client
+        .get("/v1/admin/orphaned-packages?scope=other")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().unwrap(), "[]");
+}
+
+#[test]
+fn orphaned_packages_requires_admin() {
+    let client = new_client(AuthMode::DoubleApiKey {
+        read: Some(String::from("read")),
+        write: String::from("write"),
+    });
+
+    let response = client
+        .get("/v1/admin/orphaned-packages")
+        .header(Header::new("Authorization", "Bearer read"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Unauthorized,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn fsck_reports_inconsistencies() {
+    let config = test_config(
+        AuthMode::ApiKey(String::from("hello")),
+        init_test_index_remote().unwrap(),
+    );
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+
+    for package in &["biff/hello@1.0.0", "biff/changed@1.0.0", "biff/gone@1.0.0"] {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(PackageBuilder::new(*package).contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+
+    // Simulate storage drifting away from the index behind the registry's back.
+    let changed = PackageBuilder::new("biff/changed@1.0.0").with_dep("Hello", "biff/hello@1.0.0");
+    fs_err::write(
+        package_path.join("biff/changed/1.0.0.zip"),
+        changed.contents().data(),
+    )
+    .unwrap();
+    fs_err::remove_file(package_path.join("biff/gone/1.0.0.zip")).unwrap();
+
+    let response = client
+        .get("/v1/admin/fsck")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let report: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(report["checked"], 3);
+    assert_eq!(report["missing"], serde_json::json!(["biff/gone@1.0.0"]));
+    assert_eq!(report["invalid"][0]["package"], "biff/changed@1.0.0");
+    assert_eq!(report["invalid"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        report["orphaned"],
+        serde_json::json!(["biff/minimal@0.1.0"])
+    );
+}
+
+#[test]
+fn publish_retry_after_partial_failure() {
+    let config = test_config(
+        AuthMode::ApiKey(String::from("hello")),
+        init_test_index_remote().unwrap(),
+    );
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+
+    let publish = |package: &PackageBuilder| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let package = PackageBuilder::new("biff/hello@1.0.0");
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(publish(&package));
+
+    let stored_path = package_path.join("biff/hello/1.0.0.zip");
+    assert!(stored_path.exists());
+    assert!(!package_path.join("biff/hello/1.0.0.zip.staged").exists());
+
+    // Publishing the same package again is a conflict as usual.
+    Expectation {
+        status: Status::Conflict,
+        content_type: ContentType::JSON,
+    }
+    .assert(publish(&package));
+
+    // Pretend the package made it into the index but not into storage, along
+    // with some leftovers from an earlier attempt.
+    fs_err::remove_file(&stored_path).unwrap();
+    fs_err::write(package_path.join("biff/hello/1.0.0.zip.staged"), "junk").unwrap();
+
+    // A different package with the same version still can't take its place.
+    let impostor = PackageBuilder::new("biff/hello@1.0.0").with_dep("Other", "biff/other@1.0.0");
+    Expectation {
+        status: Status::Conflict,
+        content_type: ContentType::JSON,
+    }
+    .assert(publish(&impostor));
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(publish(&package));
+
+    assert!(stored_path.exists());
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::GZIP,
+    }
+    .assert(response);
+}
+
+#[rocket::async_test]
+async fn concurrent_publishes() {
+    let config = test_config(
+        AuthMode::ApiKey(String::from("hello")),
+        init_test_index_remote().unwrap(),
+    );
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(config));
+    let client = asynchronous::Client::tracked(server(figment))
+        .await
+        .expect("valid rocket instance");
+
+    let publish = |identity: String| {
+        let contents = PackageBuilder::new(identity).contents();
+        let client = &client;
+
+        async move {
+            client
+                .post("/v1/publish")
+                .header(Accept::JSON)
+                .body(contents.data())
+                .header(Header::new("Authorization", "Bearer hello"))
+                .dispatch()
+                .await
+                .status()
+        }
+    };
+
+    // Different versions of the same package should all make it in.
+    let statuses = futures::future::join_all(
+        (0..10).map(|minor| publish(format!("biff/racing@1.{}.0", minor))),
+    )
+    .await;
+    assert!(statuses.iter().all(|status| *status == Status::Ok));
+
+    // Only one publish of the same version can win.
+    let statuses =
+        futures::future::join_all((0..5).map(|_| publish(String::from("biff/racing@2.0.0")))).await;
+    assert_eq!(statuses.iter().filter(|s| **s == Status::Ok).count(), 1);
+    assert_eq!(
+        statuses.iter().filter(|s| **s == Status::Conflict).count(),
+        4
+    );
+
+    let response = client
+        .get("/v1/package-metadata/biff/racing")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let metadata: serde_json::Value =
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(metadata["versions"].as_array().unwrap().len(), 11);
+}
+
+#[test]
+fn publish_retries_after_remote_changes() {
+    let remote = init_test_index_remote().unwrap();
+    let first = PackageIndex::new_temp(&remote, None).unwrap();
+    let second = PackageIndex::new_temp(&remote, None).unwrap();
+
+    first
+        .publish(PackageBuilder::new("biff/racing@1.0.0").manifest())
+        .unwrap();
+
+    // The second copy of the index hasn't seen the first publish, so its push
+    // is rejected and has to be replayed on top.
+    second
+        .publish(PackageBuilder::new("biff/racing@1.1.0").manifest())
+        .unwrap();
+
+    // Publishing a version somebody else already pushed fails instead.
+    first
+        .publish(PackageBuilder::new("biff/racing@1.2.0").manifest())
+        .unwrap();
+    assert!(second
+        .publish(PackageBuilder::new("biff/racing@1.2.0").manifest())
+        .is_err());
+
+    // Replaying a publish has to recreate the directory for a brand-new scope,
+    // since resetting to the remote's changes removes it.
+    first
+        .publish(PackageBuilder::new("biff/racing@1.3.0").manifest())
+        .unwrap();
+    second
+        .publish(PackageBuilder::new("zap/fresh@1.0.0").manifest())
+        .unwrap();
+
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    let metadata = index
+        .get_package_metadata(&"biff/racing".parse().unwrap())
+        .unwrap();
+    assert_eq!(metadata.versions.len(), 4);
+
+    let metadata = index
+        .get_package_metadata(&"zap/fresh".parse().unwrap())
+        .unwrap();
+    assert_eq!(metadata.versions.len(), 1);
+
+    // Deprecations are pushed the same way.
+    first
+        .publish(PackageBuilder::new("biff/racing@1.4.0").manifest())
+        .unwrap();
+    second
+        .deprecate(
+            &"biff/racing".parse().unwrap(),
+            Deprecation {
+                versions: None,
+                message: String::from("Use biff/util instead"),
+                replacement: None,
+            },
+        )
+        .unwrap();
+
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    let deprecations = index
+        .get_deprecations(&"biff/racing".parse().unwrap())
+        .unwrap();
+    assert_eq!(deprecations.len(), 1);
+}
+
+#[test]
+fn publish_size_limit() {
+    use rocket::data::ToByteUnit;
+
+    // Random text doesn't compress well, so this makes for a big archive.
+    let mut state: u32 = 0x2545_f491;
+    let big_file: String = (0..4 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (b'!' + (state % 90) as u8) as char
+        })
+        .collect();
+    let big_package = PackageBuilder::new("biff/big@1.0.0").with_file("big.txt", big_file);
+    assert!(big_package.contents().data().len() > 2.mebibytes());
+
+    let publish = |client: &Client| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(big_package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .status()
+    };
+
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+    assert_eq!(publish(&client), Status::PayloadTooLarge);
+
+    let mut config = test_config(
+        AuthMode::ApiKey(String::from("hello")),
+        init_test_index_remote().unwrap(),
+    );
+    config.max_package_size = Some(10.mebibytes());
+    let client = new_client_with_config(config);
+    assert_eq!(publish(&client), Status::Ok);
+}
+
+#[test]
+fn health_check() {
+    let client = new_client(AuthMode::Unauthenticated);
+    let response = client.get("/health").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let health: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(
+        health,
+        serde_json::json!({
+            "status": "ok",
+            "checks": {
+                "index": "ok",
+                "storage": "ok",
+                "search": "ok",
+            },
+        })
+    );
+}
+
+#[test]
+fn prometheus_metrics() {
+    let client = new_client(AuthMode::Unauthenticated);
+
+    let response = client.get("/").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let downloaded = response.into_bytes().unwrap().len();
+
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let metrics = response.into_string().unwrap();
+    assert!(metrics.contains(r#"wally_http_requests_total{route="/",method="GET",status="200"} 1"#));
+    assert!(metrics.contains(
+        r#"wally_http_request_duration_seconds_count{route="/v1/package-contents/<scope>/<name>/<version>"} 1"#
+    ));
+    assert!(metrics.contains("wally_downloads_total 1\n"));
+    assert!(metrics.contains(&format!("wally_download_bytes_total {}\n", downloaded)));
+}
+
+#[test]
+fn audit_log() {
+    let audit_log_path = tempfile::tempdir().unwrap().into_path().join("audit.jsonl");
+
+    let mut config = test_config(
+        AuthMode::ApiKey(String::from("hello")),
+        init_test_index_remote().unwrap(),
+    );
+    config.audit_log_path = Some(audit_log_path.clone());
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    let response = client
+        .get("/v1/admin/audit-log?action=publish")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let entries: serde_json::Value =
+        serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(
+        entries[0]["actor"],
+        serde_json::json!({ "type": "api-key" })
+    );
+    assert_eq!(
+        entries[0]["action"],
+        serde_json::json!({ "type": "publish", "package": "biff/hello@1.0.0" })
+    );
+
+    let response = client
+        .get("/v1/admin/audit-log?action=add-scope-owner")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.into_string().unwrap(), "[]");
+
+    // Entries are kept on disk so that they survive restarts.
+    let persisted = fs_err::read_to_string(&audit_log_path).unwrap();
+    assert_eq!(persisted.lines().count(), 1);
+}
+
+#[test]
+fn rate_limit() {
+    let mut config = test_config(
+        AuthMode::DoubleApiKey {
+            read: Some(String::from("reader")),
+            write: String::from("writer"),
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.rate_limit.search = Some(RateLimit {
+        requests: 2,
+        period: 60,
+    });
+    let client = new_client_with_config(config);
+
+    let search = |token: &str| {
+        client
+            .get("/v1/package-search?query=hello")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+    };
+
+    assert_eq!(search("reader").status(), Status::Ok);
+    assert_eq!(search("reader").status(), Status::Ok);
+
+    let response = search("reader");
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let retry_after: u64 = response
+        .headers()
+        .get_one("Retry-After")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+
+    // Valid tokens have their own budget, but made-up tokens share the budget
+    // of the address they come from, so they can't be used to get around it.
+    assert_eq!(search("made-up-1").status(), Status::Unauthorized);
+    assert_eq!(search("made-up-2").status(), Status::Unauthorized);
+    assert_eq!(search("made-up-3").status(), Status::TooManyRequests);
+
+    // Routes without a budget aren't limited.
+    assert_eq!(client.get("/").dispatch().status(), Status::Ok);
+}
+
+/// Serve just enough of the GitHub API to verify tokens, counting how many
+/// requests it gets.
+fn spawn_github_stub() -> (url::Url, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = url::Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    let counter = Arc::clone(&requests);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = BufReader::new(stream.unwrap());
+            counter.fetch_add(1, Ordering::SeqCst);
+
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).unwrap();
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            stream
+                .by_ref()
+                .take(content_length)
+                .read_to_end(&mut Vec::new())
+                .unwrap();
+
+            let body = if request_line.starts_with("GET /user ") {
+                r#"{"login":"biff","id":1}"#
+            } else {
+                r#"{"id":1,"app":{"client_id":"client"}}"#
+            };
+
+            write!(
+                stream.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    (url, requests)
+}
+
+#[test]
+fn github_token_cache() {
+    let (api_url, github_requests) = spawn_github_stub();
+    let client = new_client(AuthMode::GithubOAuth {
+        client_id: String::from("client"),
+        client_secret: String::from("secret"),
+        api_url: Some(api_url),
+    });
+
+    let audit_log = |token: &str| {
+        client
+            .get("/v1/admin/audit-log")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .status()
+    };
+
+    // GitHub users can't see the audit log, but they have to be verified
+    // before we can tell who they are.
+    assert_eq!(audit_log("first"), Status::Forbidden);
+    assert_eq!(github_requests.load(Ordering::SeqCst), 2);
+
+    assert_eq!(audit_log("first"), Status::Forbidden);
+    assert_eq!(github_requests.load(Ordering::SeqCst), 2);
+
+    assert_eq!(audit_log("second"), Status::Forbidden);
+    assert_eq!(github_requests.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn cors_defaults() {
+    let client = new_client(AuthMode::Unauthenticated);
+
+    let response = client
+        .get("/v1/package-search?query=hello")
+        .header(Header::new("Origin", "https://example.com"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some("*")
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Expose-Headers"),
+        Some("Wally-Total-Count")
+    );
+
+    let response = client
+        .options("/v1/package-search")
+        .header(Header::new("Origin", "https://example.com"))
+        .header(Header::new("Access-Control-Request-Method", "GET"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some("*")
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Methods"),
+        Some("GET")
+    );
+}
+
+#[test]
+fn cors_allowed_origins() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.cors = CorsConfig {
+        allowed_origins: vec![String::from("https://wally.example.com")],
+        allowed_methods: vec![String::from("GET"), String::from("POST")],
+        allowed_headers: vec![String::from("Authorization")],
+    };
+    let client = new_client_with_config(config);
+
+    let preflight = |origin: &str, method: &str, headers: &str| {
+        client
+            .options("/v1/package-search")
+            .header(Header::new("Origin", origin.to_owned()))
+            .header(Header::new(
+                "Access-Control-Request-Method",
+                method.to_owned(),
+            ))
+            .header(Header::new(
+                "Access-Control-Request-Headers",
+                headers.to_owned(),
+            ))
+            .dispatch()
+    };
+
+    let response = preflight("https://wally.example.com", "POST", "authorization");
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some("https://wally.example.com")
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Methods"),
+        Some("GET, POST")
+    );
+    assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+
+    let response = preflight("https://evil.example.com", "GET", "");
+    assert_eq!(response.status(), Status::Forbidden);
+    assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+
+    let response = preflight("https://wally.example.com", "DELETE", "");
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = preflight("https://wally.example.com", "GET", "X-Custom");
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Other origins still get a response, but browsers won't let them read it.
+    let response = client
+        .get("/v1/package-search?query=hello")
+        .header(Header::new("Origin", "https://evil.example.com"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+}